@@ -0,0 +1,552 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{Result, SupervisorError};
+
+const CONFIG_ENV: &str = "CONFIG";
+const CONFIG_DEFAULT: &str = "config.toml";
+const EMBEDDED_SCHEMES: [&str; 3] = ["mem", "rocksdb", "file"];
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub db: DbConfig,
+    pub namespaces: Namespaces,
+    pub tables: Tables,
+    pub roles: Roles,
+    pub joins: Joins,
+    pub embedded: Embedded,
+    pub log: Log,
+    pub http: Http,
+    pub backup: Backup,
+    pub scope: Scope,
+    pub migrations: Migrations,
+    pub rollout: Rollout,
+    pub centers: Centers,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    /// `host:port` of a remote server, or a full endpoint such as
+    /// `ws://host:port`, `wss://host:port`, `mem://` or `rocksdb://path/to/data`.
+    pub host: String,
+    pub port: Option<u16>,
+    pub user: String,
+    pub pass: String,
+    /// Reach every project database as a user defined on that database only,
+    /// root is kept for provisioning. Has no effect on an embedded engine.
+    pub tenant_users: bool,
+    pub tls: Tls,
+}
+
+/// Certificates for `wss://` and `https://` servers. Without a CA bundle the
+/// server has to present a certificate from a public authority.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Tls {
+    /// PEM bundle of the authorities the server certificate is checked
+    /// against, in place of the public ones.
+    pub ca: Option<PathBuf>,
+    /// PEM client certificate chain, for servers that ask for one.
+    pub cert: Option<PathBuf>,
+    /// PEM private key of `cert`.
+    pub key: Option<PathBuf>,
+    /// The above read into a client configuration by [`Config::load`], a
+    /// broken certificate stops the start instead of the first connection.
+    #[serde(skip)]
+    pub client: Option<rustls::ClientConfig>,
+}
+
+impl Tls {
+    pub fn is_configured(&self) -> bool {
+        self.ca.is_some() || self.cert.is_some() || self.key.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Namespaces {
+    /// Namespace holding the supervisor's own databases.
+    pub global: String,
+    /// Database with centers, projects, users and joins.
+    pub main: String,
+    /// Database copied into every new project.
+    pub template: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Tables {
+    pub centers: String,
+    pub projects: String,
+    pub join: String,
+    pub events: String,
+    pub users: String,
+    pub scores: String,
+    /// In the main database, one record per deleted project and its backup.
+    pub audit: String,
+    /// In the template and every project, one record per applied migration.
+    pub migrations: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Roles {
+    /// Roles that get a user inside the project database when they join.
+    pub participants: Vec<String>,
+}
+
+/// How often the joins are compared with the users of every project.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Joins {
+    /// Seconds between two passes, `0` only compares them on startup and
+    /// after a lost live query.
+    pub reconcile_interval: u64,
+}
+
+/// Files imported into the template and main databases when the supervisor
+/// runs its own embedded engine.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Embedded {
+    pub main: Option<PathBuf>,
+    pub template: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Log {
+    /// `tracing` filter directive, e.g. `info` or `q_api_super=debug,warn`.
+    /// `RUST_LOG` wins over it when set.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of every open span.
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Http {
+    /// Address serving `/metrics`, `/healthz` and `/readyz`.
+    pub addr: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Backup {
+    /// Where the archive of a deleted project is written, as
+    /// `<dir>/<center>/<project>-<timestamp>.surql.gz`.
+    pub dir: PathBuf,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Scope {
+    /// Seconds the previous secret of a project keeps signing in users after
+    /// its token was changed, `0` drops it right away.
+    pub token_grace: u64,
+}
+
+/// Versioned schema changes for the template and every project.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Migrations {
+    /// Directory of `<version>_<name>.surql` files. Without it the migrations
+    /// recorded in the template database are replayed on the projects.
+    pub dir: Option<PathBuf>,
+}
+
+/// How pending migrations reach the existing projects.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Rollout {
+    /// `center/project` migrated first, the others only follow once all of
+    /// them passed.
+    pub canaries: Vec<String>,
+    /// Projects per wave after the canaries, `0` migrates the rest at once.
+    /// The projects of a wave are migrated together, a failure among them
+    /// halts the rollout once the wave is through.
+    pub wave: usize,
+    /// Seconds to wait after each wave before the next one starts, for its
+    /// projects to show a problem their checks did not catch.
+    pub soak: u64,
+    /// SurrealQL run in every migrated project, an error halts the rollout.
+    pub checks: Vec<String>,
+    /// How far the rollout goes when the supervisor starts.
+    pub on_start: OnStart,
+}
+
+/// The projects the rollout at startup migrates. The template is migrated
+/// either way and every project gets the supervisor's own migrations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnStart {
+    /// None, they wait for `q-api-super rollout`.
+    Off,
+    /// Only the canaries, the rest waits for `q-api-super rollout`. Without
+    /// canaries every wave.
+    #[default]
+    Canaries,
+    /// Every wave, as `q-api-super rollout` would.
+    All,
+}
+
+/// What the supervisor does with the namespace of every center.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Centers {
+    /// User defined on the namespace of every center with the `EDITOR` role.
+    pub editor: String,
+    /// Its password, no user is defined while it is empty.
+    pub editor_pass: String,
+    pub on_delete: OnDelete,
+}
+
+/// What happens to the projects of a deleted center.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnDelete {
+    /// The deletion fails while the center has projects.
+    #[default]
+    Block,
+    /// The projects are deleted with the center, each one backed up.
+    Cascade,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost:8000".to_string(),
+            port: None,
+            user: "root".to_string(),
+            pass: "root".to_string(),
+            tenant_users: true,
+            tls: Tls::default(),
+        }
+    }
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        Self {
+            global: "global".to_string(),
+            main: "main".to_string(),
+            template: "interventions".to_string(),
+        }
+    }
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self {
+            centers: "centers".to_string(),
+            projects: "projects".to_string(),
+            join: "join".to_string(),
+            events: "events".to_string(),
+            users: "users".to_string(),
+            scores: "scores".to_string(),
+            audit: "audit".to_string(),
+            migrations: "migration".to_string(),
+        }
+    }
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        Self {
+            participants: vec!["parti".to_string(), "guest".to_string()],
+        }
+    }
+}
+
+impl Default for Joins {
+    fn default() -> Self {
+        Self {
+            reconcile_interval: 300,
+        }
+    }
+}
+
+impl Default for Centers {
+    fn default() -> Self {
+        Self {
+            editor: "editor".to_string(),
+            editor_pass: String::new(),
+            on_delete: OnDelete::Block,
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:9000".to_string(),
+        }
+    }
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("backups"),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file named by `$CONFIG` (or `config.toml` when present) and
+    /// applies the environment overrides on top.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var(CONFIG_ENV).ok();
+
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(CONFIG_DEFAULT).exists() => Self::from_file(CONFIG_DEFAULT)?,
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.db.tls.client = crate::tls::client_config(&config.db.tls)?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            SupervisorError::config(format!("Failed to read {}: {e}", path.display()))
+        })?;
+
+        toml::from_str(&content).map_err(|e| {
+            SupervisorError::config(format!("Failed to parse {}: {e}", path.display()))
+        })
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(host) = env("DB_HOST")? {
+            self.db.host = host;
+        }
+        if let Some(port) = env("DB_PORT")? {
+            self.db.port = Some(
+                port.parse()
+                    .map_err(|_| SupervisorError::config("DB_PORT is not a port"))?,
+            );
+        }
+        if let Some(user) = env("DB_USER")? {
+            self.db.user = user;
+        }
+        if let Some(pass) = env("DB_PASS")? {
+            self.db.pass = pass;
+        }
+        if let Some(ca) = env("DB_TLS_CA")? {
+            self.db.tls.ca = Some(PathBuf::from(ca));
+        }
+        if let Some(cert) = env("DB_TLS_CERT")? {
+            self.db.tls.cert = Some(PathBuf::from(cert));
+        }
+        if let Some(key) = env("DB_TLS_KEY")? {
+            self.db.tls.key = Some(PathBuf::from(key));
+        }
+
+        if let Some(global) = env("DB_NS_GLOBAL")? {
+            self.namespaces.global = global;
+        }
+        if let Some(main) = env("DB_DB_MAIN")? {
+            self.namespaces.main = main;
+        }
+        if let Some(template) = env("DB_DB_TEMPLATE")? {
+            self.namespaces.template = template;
+        }
+
+        if let Some(dir) = env("BACKUP_DIR")? {
+            self.backup.dir = PathBuf::from(dir);
+        }
+
+        if let Some(dir) = env("MIGRATIONS_DIR")? {
+            self.migrations.dir = Some(PathBuf::from(dir));
+        }
+
+        if let Some(grace) = env("SCOPE_TOKEN_GRACE")? {
+            self.scope.token_grace = grace
+                .parse()
+                .map_err(|_| SupervisorError::config("SCOPE_TOKEN_GRACE is not seconds"))?;
+        }
+
+        if let Some(pass) = env("CENTER_EDITOR_PASS")? {
+            self.centers.editor_pass = pass;
+        }
+
+        if let Some(addr) = env("HTTP_ADDR")? {
+            self.http.addr = addr;
+        }
+
+        if let Some(level) = env("LOG_LEVEL")? {
+            self.log.level = level;
+        }
+        if let Some(format) = env("LOG_FORMAT")? {
+            self.log.format = match format.as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(SupervisorError::config("LOG_FORMAT is not text or json")),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Endpoint handed to `any::connect`, a bare `host:port` means websocket,
+    /// over TLS once `db.tls` has a certificate.
+    pub fn endpoint(&self) -> String {
+        if self.is_embedded() {
+            return self.db.host.clone();
+        }
+
+        let address = match self.db.port {
+            Some(port) => format!("{}:{}", self.db.host, port),
+            None => self.db.host.clone(),
+        };
+
+        if address.contains("://") {
+            address
+        } else if self.db.tls.is_configured() {
+            format!("wss://{address}")
+        } else {
+            format!("ws://{address}")
+        }
+    }
+
+    /// The same server over HTTP, for clients that switch their session.
+    pub fn http_endpoint(&self) -> String {
+        let endpoint = self.endpoint();
+
+        if let Some(address) = endpoint.strip_prefix("wss://") {
+            format!("https://{address}")
+        } else if let Some(address) = endpoint.strip_prefix("ws://") {
+            format!("http://{address}")
+        } else {
+            endpoint
+        }
+    }
+
+    /// Whether the endpoint is an engine running inside this process.
+    pub fn is_embedded(&self) -> bool {
+        self.db
+            .host
+            .split_once("://")
+            .is_some_and(|(scheme, _)| EMBEDDED_SCHEMES.contains(&scheme))
+    }
+}
+
+/// Reads `$NAME`, falling back to the content of the file named by
+/// `$NAME_FILE` so secrets can be mounted instead of passed around.
+fn env(name: &str) -> Result<Option<String>> {
+    if let Ok(value) = std::env::var(name) {
+        if !value.is_empty() {
+            return Ok(Some(value));
+        }
+    }
+
+    match std::env::var(format!("{name}_FILE")) {
+        Ok(path) if !path.is_empty() => std::fs::read_to_string(&path)
+            .map(|value| Some(value.trim_end().to_string()))
+            .map_err(|e| {
+                SupervisorError::config(format!("Failed to read {name}_FILE {path}: {e}"))
+            }),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn partial_file_keeps_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [db]
+            host = "db.staging:8000"
+
+            [namespaces]
+            global = "staging"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.db.host, "db.staging:8000");
+        assert_eq!(config.db.user, "root");
+        assert_eq!(config.namespaces.global, "staging");
+        assert_eq!(config.namespaces.main, "main");
+        assert_eq!(config.tables.join, "join");
+        assert_eq!(config.roles.participants, ["parti", "guest"]);
+        assert_eq!(config.log.format, LogFormat::Text);
+    }
+
+    #[test]
+    fn endpoint_defaults_to_websocket() {
+        let mut config = Config::default();
+        assert_eq!(config.endpoint(), "ws://localhost:8000");
+        assert!(!config.is_embedded());
+
+        config.db.host = "mem://".to_string();
+        assert_eq!(config.endpoint(), "mem://");
+        assert!(config.is_embedded());
+
+        config.db.host = "rocksdb://data/super.db".to_string();
+        assert!(config.is_embedded());
+    }
+
+    #[test]
+    fn certificates_switch_to_tls() {
+        let mut config = Config::default();
+        assert_eq!(config.http_endpoint(), "http://localhost:8000");
+
+        config.db.tls.ca = Some(PathBuf::from("ca.pem"));
+        assert_eq!(config.endpoint(), "wss://localhost:8000");
+        assert_eq!(config.http_endpoint(), "https://localhost:8000");
+
+        // an explicit scheme is kept as it is
+        config.db.host = "ws://db:8000".to_string();
+        assert_eq!(config.endpoint(), "ws://db:8000");
+        assert_eq!(config.http_endpoint(), "http://db:8000");
+    }
+
+    #[test]
+    fn unreadable_certificates_fail_the_load() {
+        let dir = tempdir::TempDir::new("config").unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[db.tls]\nca = \"missing.pem\"\n").unwrap();
+
+        // the only test reading $CONFIG
+        std::env::set_var(CONFIG_ENV, &path);
+        let loaded = Config::load();
+        std::env::remove_var(CONFIG_ENV);
+
+        assert!(matches!(loaded.unwrap_err().kind, ErrorKind::Config(_)));
+    }
+
+    #[test]
+    fn example_file_parses() {
+        Config::from_file("config.example.toml").unwrap();
+    }
+}
@@ -0,0 +1,877 @@
+use std::collections::HashMap;
+use std::future::{Future, IntoFuture};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use surrealdb::engine::any::{self, Any};
+use surrealdb::method::{Query, QueryStream};
+use surrealdb::opt::auth::{Database, Jwt, Root};
+use surrealdb::sql::Value;
+use surrealdb::{Notification, Surreal};
+use tokio::sync::{Mutex, MutexGuard, OnceCell};
+use tokio::time::{self, Interval, MissedTickBehavior};
+use tracing::{error, warn};
+
+use crate::config::Config;
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::ident::Ident;
+use crate::shutdown::Shutdown;
+use crate::{health, metrics, tls};
+
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The engine shared by every manager when running embedded, a second
+/// `any::connect("mem://")` would open a different, empty datastore.
+static EMBEDDED: OnceCell<Surreal<Any>> = OnceCell::const_new();
+
+/// Held while a client has its connection session switched, see [`exclusive`].
+static EXCLUSIVE: Mutex<()> = Mutex::const_new(());
+
+/// The user the supervisor defines on every project database.
+const TENANT_USER: &str = "supervisor";
+
+/// How long provisioning and signing in as a tenant user may take.
+const TENANT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One client per `center/project`, shared by the managers, see [`tenant`].
+static TENANTS: Lazy<Mutex<HashMap<String, Arc<OnceCell<Tenant>>>>> = Lazy::new(Mutex::default);
+
+/// The users of `INFO FOR DB`.
+#[derive(Deserialize)]
+struct DbUsers {
+    users: HashMap<String, String>,
+}
+
+/// Opens a client and signs in as configured, namespaces are chosen per query
+/// through [`Session`].
+pub async fn connect(config: &Config) -> Surreal<Any> {
+    if config.is_embedded() {
+        return EMBEDDED
+            .get_or_init(|| async {
+                let db = any::connect(config.endpoint())
+                    .await
+                    .expect("Failed to start embedded database");
+
+                seed(&db, config).await;
+
+                db
+            })
+            .await
+            .clone();
+    }
+
+    let endpoint = remote(config, config.endpoint()).expect("Failed to read the TLS certificates");
+    let db = any::connect(endpoint)
+        .await
+        .expect("Failed to connect to database");
+
+    db.signin(Root {
+        username: config.db.user.as_str(),
+        password: config.db.pass.as_str(),
+    })
+    .await
+    .expect("Failed to signin");
+
+    db
+}
+
+/// `any::connect` target for the configured server, carrying the `db.tls`
+/// certificates when there are any. They are read here only when the
+/// configuration did not come from [`Config::load`].
+pub(crate) fn remote(
+    config: &Config,
+    endpoint: String,
+) -> Result<(String, surrealdb::opt::Config)> {
+    let client = match &config.db.tls.client {
+        Some(client) => Some(client.clone()),
+        None => tls::client_config(&config.db.tls)?,
+    };
+
+    let mut options = surrealdb::opt::Config::new();
+    if let Some(client) = client {
+        options = options.rustls(client);
+    }
+
+    Ok((endpoint, options))
+}
+
+/// Defines the supervisor's own databases on a fresh embedded engine and
+/// imports the configured seed files into them.
+async fn seed(db: &Surreal<Any>, config: &Config) {
+    let global = &config.namespaces.global;
+    let seeds = [
+        (&config.namespaces.main, &config.embedded.main),
+        (&config.namespaces.template, &config.embedded.template),
+    ];
+
+    for (database, file) in seeds {
+        let session = Session::new(db, global, database);
+
+        session
+            .execute(format!(
+                "DEFINE NAMESPACE {}; DEFINE DATABASE {};",
+                Ident::from(global),
+                Ident::from(database)
+            ))
+            .await
+            .expect("Failed to define embedded database");
+
+        if let Some(file) = file {
+            import_file(&session, file)
+                .await
+                .expect("Failed to seed embedded database");
+        }
+    }
+}
+
+async fn import_file(session: &Session, file: &Path) -> Result<(), String> {
+    let sql = tokio::fs::read_to_string(file)
+        .await
+        .map_err(|e| format!("Failed to read {}: {e}", file.display()))?;
+
+    session
+        .execute(sql)
+        .await
+        .map_err(|e| format!("Failed to import {}: {e}", file.display()))
+}
+
+/// A client that may switch its connection session with `use_ns`/`use_db`,
+/// which `export` and `import` rely on.
+///
+/// Remote servers get a fresh HTTP client, the embedded engine can only be
+/// reached through the shared client, so holders are serialized instead.
+pub struct Exclusive {
+    db: Surreal<Any>,
+    /// Only held for the embedded engine.
+    _guard: Option<MutexGuard<'static, ()>>,
+}
+
+impl Deref for Exclusive {
+    type Target = Surreal<Any>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+pub async fn exclusive(config: &Config) -> Result<Exclusive> {
+    if config.is_embedded() {
+        let guard = EXCLUSIVE.lock().await;
+
+        return Ok(Exclusive {
+            db: connect(config).await,
+            _guard: Some(guard),
+        });
+    }
+
+    let db = any::connect(remote(config, config.http_endpoint())?).await?;
+
+    db.signin(Root {
+        username: config.db.user.as_str(),
+        password: config.db.pass.as_str(),
+    })
+    .await?;
+
+    Ok(Exclusive { db, _guard: None })
+}
+
+/// Exponential backoff used between reconnection attempts.
+pub struct Backoff {
+    current: Duration,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            current: initial,
+            initial,
+            max,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+
+        delay
+    }
+
+    pub async fn wait(&mut self) {
+        time::sleep(self.next_delay()).await;
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Signs in again, retrying with backoff until the server answers.
+///
+/// The websocket client reconnects on its own but it does not restore live
+/// queries, so every caller has to re-open its subscriptions afterwards.
+pub async fn resume(db: &Surreal<Any>, config: &Config) {
+    if config.is_embedded() {
+        return;
+    }
+
+    retry_signin(|| {
+        db.signin(Root {
+            username: config.db.user.as_str(),
+            password: config.db.pass.as_str(),
+        })
+    })
+    .await;
+}
+
+async fn retry_signin<F, Fut>(signin: F)
+where
+    F: Fn() -> Fut,
+    Fut: IntoFuture<Output = surrealdb::Result<Jwt>>,
+{
+    let mut backoff = Backoff::default();
+
+    loop {
+        match time::timeout(HEALTH_TIMEOUT, signin().into_future()).await {
+            Ok(Ok(_)) => return,
+            Ok(Err(error)) => warn!(%error, "Failed to resume session"),
+            Err(_) => warn!("Timed out resuming session"),
+        }
+
+        backoff.wait().await;
+    }
+}
+
+/// The client a tenant handler reaches one project database through.
+///
+/// With `db.tenant_users` it is signed in as the supervisor's own user on
+/// that database, so whatever a handler runs cannot touch another tenant or
+/// the main database. Otherwise, and always for an embedded engine, it is
+/// the root client of the manager.
+#[derive(Clone)]
+pub struct Tenant {
+    db: Surreal<Any>,
+    center: String,
+    project: String,
+    /// Of the tenant user, `None` for the root client.
+    password: Option<String>,
+}
+
+impl Tenant {
+    /// The root client on `ns/database`, for the streams of the main
+    /// database.
+    pub fn root(db: &Surreal<Any>, ns: &str, database: &str) -> Self {
+        Self {
+            db: db.clone(),
+            center: ns.to_string(),
+            project: database.to_string(),
+            password: None,
+        }
+    }
+
+    pub fn db(&self) -> &Surreal<Any> {
+        &self.db
+    }
+
+    pub fn session(&self) -> Session {
+        Session::new(&self.db, &self.center, &self.project)
+    }
+
+    /// Like [`resume`], signing in as the tenant user again.
+    pub async fn resume(&self, config: &Config) {
+        let Some(password) = &self.password else {
+            return resume(&self.db, config).await;
+        };
+
+        retry_signin(|| self.signin(password)).await;
+    }
+
+    async fn signin(&self, password: &str) -> surrealdb::Result<Jwt> {
+        self.db
+            .signin(Database {
+                namespace: &self.center,
+                database: &self.project,
+                username: TENANT_USER,
+                password,
+            })
+            .await
+    }
+}
+
+/// The client of `center/project`, defining the tenant user and signing in
+/// on first use. `root` provisions the user and is handed out as it is when
+/// tenant users are off.
+///
+/// Only the slot of `center/project` is held while the user is provisioned
+/// and signed in, so a server that does not answer holds up that tenant for
+/// at most [`TENANT_TIMEOUT`] and never the others.
+pub async fn tenant(
+    root: &Surreal<Any>,
+    config: &Config,
+    center: &str,
+    project: &str,
+) -> Result<Tenant> {
+    let mut tenant = Tenant {
+        db: root.clone(),
+        center: center.to_string(),
+        project: project.to_string(),
+        password: None,
+    };
+
+    if config.is_embedded() || !config.db.tenant_users {
+        return Ok(tenant);
+    }
+
+    let slot = TENANTS
+        .lock()
+        .await
+        .entry(format!("{center}/{project}"))
+        .or_default()
+        .clone();
+
+    let known = slot
+        .get_or_try_init(|| async {
+            let signed_in = async {
+                let password = provision(root, center, project).await?;
+                tenant.db = any::connect(remote(config, config.endpoint())?).await?;
+                tenant.signin(&password).await?;
+                tenant.password = Some(password);
+
+                Ok::<_, SupervisorError>(tenant)
+            };
+
+            time::timeout(TENANT_TIMEOUT, signed_in)
+                .await
+                .map_err(|_| {
+                    SupervisorError::from(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "timed out signing in as the tenant user",
+                    ))
+                })?
+        })
+        .await?;
+
+    Ok(known.clone())
+}
+
+/// The session of `center/project` through its [`tenant`] client.
+pub async fn tenant_session(
+    root: &Surreal<Any>,
+    config: &Config,
+    center: &str,
+    project: &str,
+) -> Result<Session> {
+    let tenant = tenant(root, config, center, project)
+        .await
+        .tenant(center, project)?;
+
+    Ok(tenant.session())
+}
+
+/// The [`tenant`] client of a stream, retried until it signs in, `None`
+/// once `stop` is triggered.
+pub async fn tenant_until(
+    root: &Surreal<Any>,
+    config: &Config,
+    center: &str,
+    project: &str,
+    stop: &Shutdown,
+) -> Option<Tenant> {
+    let mut backoff = Backoff::default();
+
+    while !stop.is_triggered() {
+        match tenant(root, config, center, project).await {
+            Ok(tenant) => return Some(tenant),
+            Err(error) => {
+                error!(%error, "Failed to connect to the project database");
+                backoff.wait().await;
+            }
+        }
+    }
+
+    None
+}
+
+/// Defines the tenant user on `center/project` with a new random password,
+/// `EDITOR` on that database only.
+pub async fn provision(
+    root: &Surreal<Any>,
+    center: &str,
+    project: &str,
+) -> surrealdb::Result<String> {
+    let password = uuid::Uuid::new_v4().simple().to_string();
+
+    Session::new(root, center, project)
+        .execute(format!(
+            "DEFINE USER {} ON DATABASE PASSWORD '{password}' ROLES EDITOR;",
+            Ident::from(TENANT_USER)
+        ))
+        .await?;
+
+    Ok(password)
+}
+
+/// Forgets the client of a deleted or moved project and removes its tenant
+/// user, the database itself stays. The user is removed whether or not this
+/// process provisioned it, one left by an earlier run would outlive the
+/// project otherwise.
+pub async fn release(root: &Surreal<Any>, center: &str, project: &str) -> surrealdb::Result<()> {
+    TENANTS.lock().await.remove(&format!("{center}/{project}"));
+
+    let session = Session::new(root, center, project);
+    let mut res = session.query("INFO FOR DB;").await?;
+    let info: Option<DbUsers> = res.take(res.num_statements() - 1)?;
+
+    // REMOVE USER fails on a user that is not there
+    let user = Ident::from(TENANT_USER);
+    if info.is_some_and(|info| info.users.contains_key(&user.to_string())) {
+        session
+            .execute(format!("REMOVE USER {user} ON DATABASE;"))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// A namespace and database on top of a shared client.
+///
+/// `use_ns`/`use_db` change the session of the whole connection, so concurrent
+/// tenants on one client would switch it under each other. Instead every
+/// query is prefixed with its own `USE` statement, which SurrealDB scopes to
+/// that request. The prefix is the first statement of the response, so
+/// results are read with `res.take(res.num_statements() - 1)`. Both names
+/// are escaped, whatever a center or project is called.
+#[derive(Clone)]
+pub struct Session {
+    db: Surreal<Any>,
+    ns: Ident,
+    database: Ident,
+}
+
+impl Session {
+    pub fn new(db: &Surreal<Any>, ns: impl Into<Ident>, database: impl Into<Ident>) -> Self {
+        Self {
+            db: db.clone(),
+            ns: ns.into(),
+            database: database.into(),
+        }
+    }
+
+    pub fn query(&self, sql: impl AsRef<str>) -> Query<'_, Any> {
+        self.db.query(format!(
+            "USE NS {} DB {}; {}",
+            self.ns,
+            self.database,
+            sql.as_ref()
+        ))
+    }
+
+    /// Runs statements whose results are not needed, failing on the first
+    /// statement that errored.
+    pub async fn execute(&self, sql: impl AsRef<str>) -> surrealdb::Result<()> {
+        self.query(sql).await?.check()?;
+
+        Ok(())
+    }
+
+    pub async fn live<T>(&self, table: &str) -> surrealdb::Result<QueryStream<Notification<T>>>
+    where
+        T: DeserializeOwned + Unpin,
+    {
+        let mut res = self.query(format!("LIVE SELECT * FROM {table};")).await?;
+
+        res.stream(res.num_statements() - 1)
+    }
+}
+
+/// Watches a live query stream together with the connection that owns it.
+///
+/// SurrealDB keeps live queries per websocket session, so a server restart
+/// silently drops them while the stream itself stays open. The watchdog
+/// compares `session::id()` between ticks and ends the stream when the
+/// session changed or the server stopped answering.
+pub struct Watchdog {
+    db: Surreal<Any>,
+    session: Option<Value>,
+    interval: Interval,
+    shutdown: Shutdown,
+}
+
+impl Watchdog {
+    pub async fn new(db: &Surreal<Any>, shutdown: &Shutdown) -> Self {
+        Self::every(db, shutdown, HEALTH_INTERVAL).await
+    }
+
+    async fn every(db: &Surreal<Any>, shutdown: &Shutdown, period: Duration) -> Self {
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.reset();
+
+        let session = session_id(db).await;
+
+        Self {
+            db: db.clone(),
+            session,
+            interval,
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    /// Returns the next item of `stream`, or `None` once the stream ended,
+    /// the connection was lost or shutdown was triggered.
+    pub async fn next<S>(&mut self, stream: &mut S) -> Option<S::Item>
+    where
+        S: Stream + Unpin,
+    {
+        loop {
+            tokio::select! {
+                biased;
+
+                _ = self.shutdown.triggered() => return None,
+                item = stream.next() => return item,
+                _ = self.interval.tick() => {
+                    let session = session_id(&self.db).await;
+
+                    if session.is_none() || session != self.session {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The live query a [`supervise`]d stream keeps open, and the labels of its
+/// metrics. The streams of the main database have no center and project.
+#[derive(Clone, Copy)]
+pub struct Subscription<'a> {
+    pub table: &'a str,
+    pub manager: &'a str,
+    pub center: &'a str,
+    pub project: &'a str,
+}
+
+/// Keeps the live query of `subscription` open until `stop` is triggered,
+/// passing every notification to `handle`.
+///
+/// A stream that could not start or was lost is opened again with backoff,
+/// after signing in again and running `reconcile` for whatever changed in
+/// between. Failures of either are counted and logged, never fatal.
+pub async fn supervise<T, H, HF, R, RF>(
+    client: &Tenant,
+    config: &Config,
+    subscription: Subscription<'_>,
+    health: &health::Stream,
+    stop: &Shutdown,
+    handle: H,
+    reconcile: R,
+) where
+    T: DeserializeOwned + Unpin,
+    H: Fn(Notification<T>) -> HF,
+    HF: Future<Output = Result<()>>,
+    R: Fn() -> RF,
+    RF: Future<Output = Result<()>>,
+{
+    let Subscription {
+        table,
+        manager,
+        center,
+        project,
+    } = subscription;
+    let session = client.session();
+    let mut backoff = Backoff::default();
+
+    while !stop.is_triggered() {
+        match session.live::<T>(table).await {
+            Ok(mut stream) => {
+                backoff.reset();
+                let _live = metrics::LiveStream::new(manager, center, project);
+                let _connected = health.connected();
+
+                let mut watchdog = Watchdog::new(client.db(), stop).await;
+                while let Some(result) = watchdog.next(&mut stream).await {
+                    match result {
+                        Ok(notification) => {
+                            let _timer = metrics::notification(
+                                manager,
+                                center,
+                                project,
+                                &notification.action,
+                            );
+
+                            if let Err(error) = handle(notification).await {
+                                metrics::failure(manager, center, project);
+                                error!(%error);
+                            }
+                        }
+                        Err(error) => error!(%error),
+                    }
+                }
+
+                if stop.is_triggered() {
+                    break;
+                }
+
+                warn!("Lost live query, reconnecting");
+            }
+            Err(error) => {
+                error!(%error, "Failed to start live query");
+                backoff.wait().await;
+            }
+        }
+
+        client.resume(config).await;
+        if let Err(error) = reconcile().await {
+            metrics::failure(manager, center, project);
+            error!(%error);
+        }
+    }
+}
+
+async fn session_id(db: &Surreal<Any>) -> Option<Value> {
+    let query = async {
+        let mut res = db.query("RETURN session::id();").await?;
+
+        res.take::<Value>(0)
+    };
+
+    match time::timeout(HEALTH_TIMEOUT, query).await {
+        Ok(Ok(Value::None)) => Some(Value::Null),
+        Ok(Ok(id)) => Some(id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Written {
+        tenant: String,
+    }
+
+    const WRITES: usize = 50;
+    const TENANTS: [(&str, &str, &str); 2] = [
+        ("center_a", "project_a", "a"),
+        ("center_b", "project_b", "b"),
+    ];
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_sessions_never_cross_write() {
+        let db = any::connect("mem://").await.unwrap();
+
+        let mut tasks = Vec::new();
+        for n in 0..WRITES {
+            for (center, project, tenant) in TENANTS {
+                let db = db.clone();
+                let session = Session::new(&db, center, project);
+
+                tasks.push(tokio::spawn(async move {
+                    // another task switching the connection must not matter
+                    db.use_ns(center).use_db("elsewhere").await.unwrap();
+
+                    session
+                        .query("CREATE events SET tenant = $b_tenant, n = $b_n;")
+                        .bind(("b_tenant", tenant))
+                        .bind(("b_n", n))
+                        .await
+                        .unwrap()
+                        .check()
+                        .unwrap();
+                }));
+            }
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        for (center, project, tenant) in TENANTS {
+            let session = Session::new(&db, center, project);
+            let mut res = session.query("SELECT tenant FROM events;").await.unwrap();
+            let written: Vec<Written> = res.take(res.num_statements() - 1).unwrap();
+
+            assert_eq!(written.len(), WRITES);
+            assert!(written.iter().all(|w| w.tenant == tenant));
+        }
+    }
+
+    #[tokio::test]
+    async fn embedded_clients_share_the_seeded_engine() {
+        let dir = tempdir::TempDir::new("seed").unwrap();
+        let seed = dir.path().join("interventions.surql");
+        std::fs::write(
+            &seed,
+            "DEFINE FUNCTION fn::on_cron($script: string) { RETURN $script; };",
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.db.host = "mem://".to_string();
+        config.embedded.template = Some(seed);
+
+        let first = connect(&config).await;
+        let second = connect(&config).await;
+
+        let main = Session::new(&first, "global", "main");
+        main.execute("CREATE projects:one SET name = 'one';")
+            .await
+            .unwrap();
+
+        let main = Session::new(&second, "global", "main");
+        let mut res = main
+            .query("SELECT VALUE name FROM projects;")
+            .await
+            .unwrap();
+        let names: Vec<String> = res.take(res.num_statements() - 1).unwrap();
+        assert_eq!(names, ["one"]);
+
+        let template = Session::new(&second, "global", "interventions");
+        let mut res = template.query("RETURN fn::on_cron('ok');").await.unwrap();
+        let answer: Option<String> = res.take(res.num_statements() - 1).unwrap();
+        assert_eq!(answer.as_deref(), Some("ok"));
+    }
+
+    #[tokio::test]
+    async fn provisioned_users_only_open_their_database() {
+        let root = any::connect("mem://").await.unwrap();
+        let password = provision(&root, "center_a", "project_a").await.unwrap();
+
+        let credentials = |database, password| Database {
+            namespace: "center_a",
+            database,
+            username: TENANT_USER,
+            password,
+        };
+
+        assert!(root
+            .signin(credentials("project_a", "wrong"))
+            .await
+            .is_err());
+        assert!(root
+            .signin(credentials("project_b", &password))
+            .await
+            .is_err());
+        root.signin(credentials("project_a", &password))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn release_removes_users_it_did_not_cache() {
+        let root = any::connect("mem://").await.unwrap();
+        // left behind by an earlier run, this process never cached it
+        let password = provision(&root, "center_a", "project_a").await.unwrap();
+
+        release(&root, "center_a", "project_a").await.unwrap();
+
+        let signin = root
+            .signin(Database {
+                namespace: "center_a",
+                database: "project_a",
+                username: TENANT_USER,
+                password: &password,
+            })
+            .await;
+        assert!(signin.is_err());
+
+        // nothing left to remove
+        release(&root, "center_a", "project_a").await.unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_its_max_until_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn watchdog_ends_the_stream_when_the_session_changes() {
+        let db = any::connect("mem://").await.unwrap();
+        let shutdown = Shutdown::default();
+        let period = Duration::from_millis(50);
+
+        // items pass while the session stays the same
+        let mut watchdog = Watchdog::every(&db, &shutdown, period).await;
+        let mut stream = futures::stream::iter([1]).chain(futures::stream::pending());
+        assert_eq!(watchdog.next(&mut stream).await, Some(1));
+        let waited = time::timeout(period * 4, watchdog.next(&mut stream)).await;
+        assert!(waited.is_err(), "ended on an unchanged session");
+
+        // as after a reconnect, the server knows another session
+        watchdog.session = Some(Value::from("before the restart"));
+        let ended = time::timeout(period * 4, watchdog.next(&mut stream))
+            .await
+            .expect("still waiting on a stale session");
+        assert_eq!(ended, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn live_queries_stay_in_their_tenant() {
+        let db = any::connect("mem://").await.unwrap();
+
+        let mut streams = Vec::new();
+        for (center, project, tenant) in TENANTS {
+            let session = Session::new(&db, center, project);
+            let stream = session.live::<Written>("events").await.unwrap();
+
+            streams.push((session, stream, tenant));
+        }
+
+        let writers: Vec<_> = streams
+            .iter()
+            .map(|(session, _, tenant)| {
+                let session = session.clone();
+                let tenant = tenant.to_string();
+
+                tokio::spawn(async move {
+                    for _ in 0..WRITES {
+                        session
+                            .query("CREATE events SET tenant = $b_tenant;")
+                            .bind(("b_tenant", &tenant))
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        for (_, stream, tenant) in &mut streams {
+            for _ in 0..WRITES {
+                let notification = time::timeout(Duration::from_secs(5), stream.next())
+                    .await
+                    .expect("missing notification")
+                    .unwrap()
+                    .unwrap();
+
+                assert_eq!(notification.data.tenant, *tenant);
+            }
+        }
+    }
+}
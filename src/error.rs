@@ -0,0 +1,193 @@
+use std::fmt;
+
+use surrealdb::sql::Thing;
+use tokio_cron_scheduler::JobSchedulerError;
+
+pub type Result<T, E = SupervisorError> = std::result::Result<T, E>;
+
+/// Where an error happened, filled in as it travels up through the managers.
+#[derive(Debug, Default)]
+pub struct Context {
+    pub center: Option<String>,
+    pub project: Option<String>,
+    pub record: Option<String>,
+    pub query: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Db(Box<surrealdb::Error>),
+    Scheduler(JobSchedulerError),
+    Io(std::io::Error),
+    Config(String),
+    /// A record the handler depends on does not exist (anymore).
+    NotFound(&'static str),
+    /// A row came back in a shape the handler cannot use.
+    Invalid(String),
+}
+
+#[derive(Debug)]
+pub struct SupervisorError {
+    pub kind: ErrorKind,
+    pub context: Box<Context>,
+}
+
+impl SupervisorError {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            context: Box::default(),
+        }
+    }
+
+    pub fn not_found(what: &'static str) -> Self {
+        Self::new(ErrorKind::NotFound(what))
+    }
+
+    pub fn invalid(reason: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Invalid(reason.into()))
+    }
+
+    pub fn config(reason: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Config(reason.into()))
+    }
+
+    pub fn tenant(mut self, center: &str, project: &str) -> Self {
+        self.context
+            .center
+            .get_or_insert_with(|| center.to_string());
+        self.context
+            .project
+            .get_or_insert_with(|| project.to_string());
+        self
+    }
+
+    pub fn record(mut self, id: &Thing) -> Self {
+        self.context.record.get_or_insert_with(|| id.to_string());
+        self
+    }
+
+    pub fn query(mut self, sql: &str) -> Self {
+        self.context
+            .query
+            .get_or_insert_with(|| sql.split_whitespace().collect::<Vec<_>>().join(" "));
+        self
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Db(e) => write!(f, "database error: {e}"),
+            ErrorKind::Scheduler(e) => write!(f, "scheduler error: {e:?}"),
+            ErrorKind::Io(e) => write!(f, "io error: {e}"),
+            ErrorKind::Config(reason) => write!(f, "config error: {reason}"),
+            ErrorKind::NotFound(what) => write!(f, "{what} not found"),
+            ErrorKind::Invalid(reason) => write!(f, "invalid data: {reason}"),
+        }
+    }
+}
+
+impl fmt::Display for SupervisorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+
+        let Context {
+            center,
+            project,
+            record,
+            query,
+        } = self.context.as_ref();
+
+        if let (Some(center), Some(project)) = (center, project) {
+            write!(f, "; tenant = {center}/{project}")?;
+        }
+        if let Some(record) = record {
+            write!(f, "; record = {record}")?;
+        }
+        if let Some(query) = query {
+            write!(f, "; query = {query}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for SupervisorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Db(e) => Some(e.as_ref()),
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<surrealdb::Error> for SupervisorError {
+    fn from(e: surrealdb::Error) -> Self {
+        Self::new(ErrorKind::Db(Box::new(e)))
+    }
+}
+
+impl From<JobSchedulerError> for SupervisorError {
+    fn from(e: JobSchedulerError) -> Self {
+        Self::new(ErrorKind::Scheduler(e))
+    }
+}
+
+impl From<std::io::Error> for SupervisorError {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(ErrorKind::Io(e))
+    }
+}
+
+/// Adds [`Context`] to any error that converts into a [`SupervisorError`].
+pub trait ResultExt<T> {
+    fn tenant(self, center: &str, project: &str) -> Result<T>;
+    fn record(self, id: &Thing) -> Result<T>;
+    fn query(self, sql: &str) -> Result<T>;
+}
+
+impl<T, E: Into<SupervisorError>> ResultExt<T> for Result<T, E> {
+    fn tenant(self, center: &str, project: &str) -> Result<T> {
+        self.map_err(|e| e.into().tenant(center, project))
+    }
+
+    fn record(self, id: &Thing) -> Result<T> {
+        self.map_err(|e| e.into().record(id))
+    }
+
+    fn query(self, sql: &str) -> Result<T> {
+        self.map_err(|e| e.into().query(sql))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_includes_context() {
+        let id = Thing::from(("events", "tick"));
+        let error = Err::<(), _>(SupervisorError::not_found("event"))
+            .record(&id)
+            .query("SELECT *\n    FROM $b_id;")
+            .tenant("center", "project")
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "event not found; tenant = center/project; record = events:tick; query = SELECT * FROM $b_id;"
+        );
+    }
+
+    #[test]
+    fn inner_context_wins() {
+        let error = SupervisorError::invalid("no role")
+            .tenant("inner", "one")
+            .tenant("outer", "two");
+
+        assert_eq!(error.context.center.as_deref(), Some("inner"));
+        assert_eq!(error.context.project.as_deref(), Some("one"));
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::default);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Connecting,
+    Connected,
+    /// The task owning the stream returned or panicked.
+    Dead,
+}
+
+/// A stream name and the registration it belongs to, a name registered
+/// again gets a new one.
+type Key = (String, u64);
+
+/// Live streams of every manager and whether the existing projects were
+/// picked up, behind `/healthz` and `/readyz`.
+#[derive(Default)]
+pub struct Registry {
+    initialized: AtomicBool,
+    registered: AtomicU64,
+    streams: Mutex<BTreeMap<Key, State>>,
+}
+
+impl Registry {
+    pub fn mark_initialized(&self) {
+        self.initialized.store(true, Ordering::Relaxed);
+    }
+
+    pub fn register(&'static self, name: impl Into<String>) -> Stream {
+        let key = (name.into(), self.registered.fetch_add(1, Ordering::Relaxed));
+        self.set(&key, State::Connecting);
+
+        Stream {
+            registry: self,
+            key,
+            retired: false,
+        }
+    }
+
+    /// Fails with the streams whose task died.
+    pub fn liveness(&self) -> Result<(), Vec<String>> {
+        let dead = self.streams_in(|state| state == State::Dead);
+
+        if dead.is_empty() {
+            Ok(())
+        } else {
+            Err(dead)
+        }
+    }
+
+    /// Fails until the existing projects are initialized and every stream
+    /// is subscribed.
+    pub fn readiness(&self) -> Result<(), Vec<String>> {
+        let mut pending = self.streams_in(|state| state != State::Connected);
+
+        if !self.initialized.load(Ordering::Relaxed) {
+            pending.insert(0, "init".to_string());
+        }
+
+        if pending.is_empty() {
+            Ok(())
+        } else {
+            Err(pending)
+        }
+    }
+
+    fn set(&self, key: &Key, state: State) {
+        self.streams.lock().unwrap().insert(key.clone(), state);
+    }
+
+    fn remove(&self, key: &Key) {
+        self.streams.lock().unwrap().remove(key);
+    }
+
+    fn streams_in(&self, filter: impl Fn(State) -> bool) -> Vec<String> {
+        self.streams
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| filter(**state))
+            .map(|((name, _), _)| name.clone())
+            .collect()
+    }
+}
+
+/// A registered live stream, reported dead once its owner drops it.
+pub struct Stream {
+    registry: &'static Registry,
+    key: Key,
+    retired: bool,
+}
+
+impl Stream {
+    /// Unregisters a stream that ended on purpose.
+    pub fn retire(mut self) {
+        self.registry.remove(&self.key);
+        self.retired = true;
+    }
+
+    /// Marks the stream as subscribed until the guard is dropped.
+    pub fn connected(&self) -> Connected<'_> {
+        self.registry.set(&self.key, State::Connected);
+
+        Connected(self)
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if !self.retired {
+            self.registry.set(&self.key, State::Dead);
+        }
+    }
+}
+
+pub struct Connected<'a>(&'a Stream);
+
+impl Drop for Connected<'_> {
+    fn drop(&mut self) {
+        self.0.registry.set(&self.0.key, State::Connecting);
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+pub fn register(name: impl Into<String>) -> Stream {
+    REGISTRY.register(name)
+}
+
+pub fn mark_initialized() {
+    REGISTRY.mark_initialized();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> &'static Registry {
+        Box::leak(Box::default())
+    }
+
+    #[test]
+    fn ready_once_initialized_and_connected() {
+        let registry = registry();
+        let stream = registry.register("projects");
+
+        assert_eq!(
+            registry.readiness(),
+            Err(vec!["init".to_string(), "projects".to_string()])
+        );
+
+        registry.mark_initialized();
+        let connected = stream.connected();
+        assert_eq!(registry.readiness(), Ok(()));
+
+        drop(connected);
+        assert_eq!(registry.readiness(), Err(vec!["projects".to_string()]));
+        assert_eq!(registry.liveness(), Ok(()));
+    }
+
+    #[test]
+    fn dropped_stream_fails_liveness() {
+        let registry = registry();
+        let stream = registry.register("events:center/project");
+
+        let task = std::thread::spawn(move || {
+            let _stream = stream;
+            panic!("stream task died");
+        });
+        assert!(task.join().is_err());
+
+        assert_eq!(
+            registry.liveness(),
+            Err(vec!["events:center/project".to_string()])
+        );
+    }
+
+    #[test]
+    fn retired_stream_is_forgotten() {
+        let registry = registry();
+        registry.mark_initialized();
+
+        registry.register("join").retire();
+
+        assert_eq!(registry.liveness(), Ok(()));
+        assert_eq!(registry.readiness(), Ok(()));
+    }
+
+    #[test]
+    fn retire_leaves_a_reregistered_stream_alone() {
+        let registry = registry();
+        registry.mark_initialized();
+
+        let old = registry.register("users:center/project");
+        let new = registry.register("users:center/project");
+        old.retire();
+
+        assert_eq!(
+            registry.readiness(),
+            Err(vec!["users:center/project".to_string()])
+        );
+
+        let _connected = new.connected();
+        assert_eq!(registry.readiness(), Ok(()));
+    }
+}
@@ -0,0 +1,41 @@
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::config::Http;
+use crate::error::Result;
+use crate::{health, metrics};
+
+/// Serves `/metrics`, `/healthz` and `/readyz` until the listener fails.
+pub async fn serve(config: &Http) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(|| async { metrics::render() }))
+        .route(
+            "/healthz",
+            get(|| async { probe(health::registry().liveness()) }),
+        )
+        .route(
+            "/readyz",
+            get(|| async { probe(health::registry().readiness()) }),
+        );
+
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!(addr = %config.addr, "Serving metrics and health checks");
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// `200 ok`, or `503` with one failing check per line.
+fn probe(result: std::result::Result<(), Vec<String>>) -> (StatusCode, String) {
+    match result {
+        Ok(()) => (StatusCode::OK, "ok\n".to_string()),
+        Err(failing) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            failing.iter().map(|name| format!("{name}\n")).collect(),
+        ),
+    }
+}
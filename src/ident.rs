@@ -0,0 +1,134 @@
+use std::fmt;
+
+use crate::error::{Result, SupervisorError};
+
+/// Longest center or project name accepted.
+const MAX_LEN: usize = 128;
+
+/// A namespace, database or user name as it is written into SurrealQL.
+///
+/// Center and project names come from records anyone with access to the
+/// main database can edit, so they are never interpolated as they are:
+/// `Display` always escapes them, a name like `x; REMOVE NAMESPACE global`
+/// stays one identifier. [`Ident::new`] additionally refuses names that
+/// cannot be a directory or a `center/project` key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ident(String);
+
+impl Ident {
+    /// Accepts any name that is not empty, not `.` or `..`, has no `/`, `\`
+    /// or control character and is at most 128 characters long.
+    pub fn new(name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+
+        let reason = if name.is_empty() {
+            Some("is empty")
+        } else if name == "." || name == ".." {
+            Some("is a relative path")
+        } else if name.chars().count() > MAX_LEN {
+            Some("is too long")
+        } else if name.contains(['/', '\\']) {
+            Some("contains a path separator")
+        } else if name.chars().any(char::is_control) {
+            Some("contains a control character")
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => Err(SupervisorError::invalid(format!("name {name:?} {reason}"))),
+            None => Ok(Self(name)),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Reads a name from the key of an `INFO` result, where SurrealDB
+    /// escapes it the same way.
+    pub fn from_info(key: &str) -> Self {
+        let name = key
+            .strip_prefix('`')
+            .and_then(|key| key.strip_suffix('`'))
+            .map(|key| key.replace("\\`", "`"))
+            .unwrap_or_else(|| key.to_string());
+
+        Self(name)
+    }
+}
+
+/// Escaping without validation, for names that were checked on their way in
+/// or that come from the configuration.
+impl From<&str> for Ident {
+    fn from(name: &str) -> Self {
+        Self(name.to_string())
+    }
+}
+
+impl From<String> for Ident {
+    fn from(name: String) -> Self {
+        Self(name)
+    }
+}
+
+impl From<&String> for Ident {
+    fn from(name: &String) -> Self {
+        Self(name.clone())
+    }
+}
+
+impl From<&Ident> for Ident {
+    fn from(ident: &Ident) -> Self {
+        ident.clone()
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", surrealdb::sql::Ident::from(self.0.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: [&str; 6] = [
+        "center a",
+        "x; REMOVE NAMESPACE global; --",
+        "it's",
+        "back`tick",
+        "ang⟩le",
+        "über",
+    ];
+
+    #[test]
+    fn plain_names_are_written_as_they_are() {
+        assert_eq!(Ident::from("center_a").to_string(), "center_a");
+        assert_eq!(Ident::from("project1").to_string(), "project1");
+    }
+
+    #[test]
+    fn hostile_names_stay_one_identifier() {
+        for name in HOSTILE {
+            let ident = Ident::new(name).unwrap();
+            let sql = format!("USE NS {ident} DB {ident};");
+
+            let statements = surrealdb::sql::parse(&sql).unwrap();
+            assert_eq!(statements.len(), 1, "{sql}");
+            assert_eq!(statements.to_string(), sql, "{name}");
+            assert_eq!(Ident::from_info(&ident.to_string()), ident);
+        }
+    }
+
+    #[test]
+    fn names_that_cannot_be_paths_are_refused() {
+        for name in ["", ".", "..", "a/b", "a\\b", "new\nline", "nul\0"] {
+            assert!(Ident::new(name).is_err(), "{name:?}");
+        }
+
+        assert!(Ident::new("x".repeat(MAX_LEN)).is_ok());
+        assert!(Ident::new("x".repeat(MAX_LEN + 1)).is_err());
+    }
+}
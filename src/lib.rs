@@ -0,0 +1,12 @@
+pub mod config;
+pub mod connection;
+pub mod error;
+pub mod health;
+pub mod http;
+pub mod ident;
+pub mod logging;
+pub mod metrics;
+pub mod models;
+pub mod modules;
+pub mod shutdown;
+pub mod tls;
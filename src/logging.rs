@@ -0,0 +1,27 @@
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{Log, LogFormat};
+use crate::error::{Result, SupervisorError};
+
+/// Installs the global subscriber, `RUST_LOG` takes precedence over the
+/// configured level.
+pub fn init(config: &Log) -> Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.level)
+            .map_err(|e| SupervisorError::config(format!("Invalid log level: {e}")))?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let installed = match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .try_init(),
+    };
+
+    installed.map_err(|e| SupervisorError::config(format!("Failed to init logging: {e}")))
+}
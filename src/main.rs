@@ -1,46 +1,124 @@
-mod models;
-mod modules;
+use std::sync::Arc;
 
-use modules::projects::manager::Credentials;
-use surrealdb::opt::auth::Root;
-
-use crate::modules::join::manager::JoinManager;
-use crate::modules::projects::manager::ProjectsManager;
-// use crate::modules::users::manager::UserManager;
+use q_api_super::config::Config;
+use q_api_super::connection;
+use q_api_super::http;
+use q_api_super::logging;
+use q_api_super::modules::centers::manager::CentersManager;
+use q_api_super::modules::join::manager::JoinManager;
+use q_api_super::modules::projects::drift;
+use q_api_super::modules::projects::manager::ProjectsManager;
+use q_api_super::modules::projects::rollout::Rollout;
+use q_api_super::shutdown::{self, Shutdown};
+// use q_api_super::modules::users::manager::UserManager;
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
-    let db_host = std::env::var("DB_HOST").unwrap_or_else(|_| "localhost:8000".to_string());
-    let db_port = std::env::var("DB_PORT").unwrap_or_else(|_| "".to_string());
-
-    let db_user = std::env::var("DB_USER").unwrap_or_else(|_| "root".to_string());
-    let db_pass = std::env::var("DB_PASS").unwrap_or_else(|_| "root".to_string());
-
-    let db_url = if db_port.is_empty() {
-        db_host
-    } else {
-        format!("{}:{}", db_host, db_port)
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
     };
 
-    let cred = Credentials {
-        user: db_user,
-        pass: db_pass,
-    };
+    if let Err(e) = logging::init(&config.log) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    if let Some(command) = std::env::args().nth(1) {
+        std::process::exit(match command.as_str() {
+            "drift" => report_drift(&config).await,
+            "rollout" => roll_out(config).await,
+            _ => {
+                eprintln!("Unknown command {command}, expected drift or rollout");
+                1
+            }
+        });
+    }
 
     // let u_manager = UserManager::new(&db_url).await;
-    let j_manager = JoinManager::new(&db_url, cred.clone()).await;
-    let mut p_manager = ProjectsManager::new(&db_url, cred).await;
+    let shutdown = Shutdown::default();
+    let j_manager = JoinManager::new(config.clone(), shutdown.clone()).await;
+    let p_manager = Arc::new(ProjectsManager::new(config.clone(), shutdown.clone()).await);
+    let mut c_manager = CentersManager::new(config.clone(), shutdown.clone()).await;
+    c_manager.listen(p_manager.clone());
+
+    tokio::spawn(async move {
+        if let Err(error) = http::serve(&config.http).await {
+            error!(%error, "HTTP server stopped");
+        }
+    });
+
+    tokio::spawn(async move {
+        shutdown::signal().await;
+
+        info!("Shutdown requested");
+        shutdown.trigger();
+    });
+
+    info!("Listening for changes, press Ctrl+C to stop");
+
+    match tokio::join!(j_manager.start(), p_manager.start(), c_manager.start()) {
+        (Ok(_), Ok(_), Ok(_)) => {}
+        (Err(error), _, _) => {
+            error!(%error, "JoinManager stopped");
+        }
+        (_, Err(error), _) => {
+            error!(%error, "ProjectsManager stopped");
+        }
+        (_, _, Err(error)) => {
+            error!(%error, "CentersManager stopped");
+        }
+    }
+
+    info!("Stopped");
+}
 
-    println!("Listening for changes...");
-    println!("Press Ctrl+C to stop.");
+/// Prints how every project differs from the template without writing
+/// anything, the exit code is 2 when one does.
+async fn report_drift(config: &Config) -> i32 {
+    let db = connection::connect(config).await;
 
-    match tokio::join!(j_manager.start(), p_manager.start()) {
-        (Ok(_), Ok(_)) => {}
-        (Err(e), _) => {
-            eprintln!("Error in JoinManager: {}", e);
+    match drift::report(&db, config, &mut std::io::stdout()).await {
+        Ok(false) => 0,
+        Ok(true) => 2,
+        Err(e) => {
+            eprintln!("{e}");
+            1
         }
-        (_, Err(e)) => {
-            eprintln!("Error in ProjectsManager: {}", e);
+    }
+}
+
+/// Pushes the pending migrations to the existing projects in waves and
+/// prints where it got, the exit code is 2 when a project halted it.
+async fn roll_out(config: Arc<Config>) -> i32 {
+    let db = connection::connect(&config).await;
+
+    let report = match Rollout::new(db, config).run().await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
         }
+    };
+
+    for target in &report.migrated {
+        println!("migrated {target}");
+    }
+
+    if report.halted.is_empty() {
+        return 0;
     }
+
+    for (target, error) in &report.halted {
+        println!("halted at {target}: {error}");
+    }
+    for target in &report.remaining {
+        println!("not migrated {target}");
+    }
+
+    2
 }
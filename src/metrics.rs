@@ -0,0 +1,195 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use surrealdb::Action;
+
+pub static NOTIFICATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_notifications_total",
+        "Live notifications received",
+        &["manager", "center", "project", "action"]
+    )
+    .unwrap()
+});
+
+pub static NOTIFICATION_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "supervisor_notification_duration_seconds",
+        "Time spent handling a live notification",
+        &["manager", "center", "project"]
+    )
+    .unwrap()
+});
+
+pub static FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_failures_total",
+        "Notifications and reconciliations that ended in an error",
+        &["manager", "center", "project"]
+    )
+    .unwrap()
+});
+
+pub static CRON_EXECUTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_cron_executions_total",
+        "fn::on_cron calls by outcome, ok or failed",
+        &["center", "project", "outcome"]
+    )
+    .unwrap()
+});
+
+pub static CRON_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "supervisor_cron_duration_seconds",
+        "Time spent in fn::on_cron",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static LIVE_STREAMS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "supervisor_live_streams",
+        "Live queries currently subscribed",
+        &["manager", "center", "project"]
+    )
+    .unwrap()
+});
+
+pub static SCHEDULED_JOBS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "supervisor_scheduled_jobs",
+        "Cron jobs held by the events scheduler",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static MIGRATIONS_PENDING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "supervisor_migrations_pending",
+        "Migrations not yet applied to the template or a project database",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static MIGRATION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_migration_failures_total",
+        "Project migrations rolled back or discarded after a failure",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static STATE_CONFLICTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_state_conflicts_total",
+        "Participant states edited in the project and the join before either was synced",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static JOIN_REPAIRS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supervisor_join_repairs_total",
+        "Joins reconciled with their project, by what was missing: user or state",
+        &["center", "project", "kind"]
+    )
+    .unwrap()
+});
+
+pub static UNJOINED_USERS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "supervisor_unjoined_users",
+        "Project users without a join, as of the last reconciliation",
+        &["center", "project"]
+    )
+    .unwrap()
+});
+
+pub static ROLLOUT_HALTED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "supervisor_rollout_halted",
+        "1 while the last migration rollout stopped at a failing project"
+    )
+    .unwrap()
+});
+
+/// Counts a notification and times its handling until the timer is dropped.
+pub fn notification(manager: &str, center: &str, project: &str, action: &Action) -> HistogramTimer {
+    let action = format!("{action:?}").to_lowercase();
+
+    NOTIFICATIONS
+        .with_label_values(&[manager, center, project, &action])
+        .inc();
+
+    NOTIFICATION_DURATION
+        .with_label_values(&[manager, center, project])
+        .start_timer()
+}
+
+pub fn failure(manager: &str, center: &str, project: &str) {
+    FAILURES
+        .with_label_values(&[manager, center, project])
+        .inc();
+}
+
+/// Marks a live query as subscribed for as long as it is held.
+pub struct LiveStream(IntGauge);
+
+impl LiveStream {
+    pub fn new(manager: &str, center: &str, project: &str) -> Self {
+        let gauge = LIVE_STREAMS.with_label_values(&[manager, center, project]);
+        gauge.inc();
+
+        Self(gauge)
+    }
+}
+
+impl Drop for LiveStream {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Every registered metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+
+    String::from_utf8(buffer).expect("Metrics are not utf-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_stream_gauge_follows_the_guard() {
+        let gauge = LIVE_STREAMS.with_label_values(&["test", "center", "project"]);
+
+        let live = LiveStream::new("test", "center", "project");
+        assert_eq!(gauge.get(), 1);
+
+        drop(live);
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[test]
+    fn render_includes_labels() {
+        drop(notification("test", "center", "project", &Action::Create));
+
+        assert!(render().contains(
+            r#"supervisor_notifications_total{action="create",center="center",manager="test",project="project"} 1"#
+        ));
+    }
+}
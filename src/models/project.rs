@@ -3,12 +3,12 @@ use surrealdb::sql::Thing;
 
 use super::center::Center;
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Project {
     pub id: Option<Thing>,
     pub name: String,
     pub center: Thing,
-    pub state: String,
+    pub state: ProjectState,
     pub token: String,
 }
 
@@ -17,6 +17,39 @@ pub struct ProjectWithCenter {
     pub id: Option<Thing>,
     pub name: String,
     pub center: Center,
-    pub state: String,
+    pub state: ProjectState,
     pub token: String,
 }
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(from = "String")]
+pub enum ProjectState {
+    Active,
+    Paused,
+    Finished,
+    /// A state the supervisor does not know, such as a typo, the project is
+    /// left as it is until it is corrected.
+    Unknown(String),
+}
+
+impl From<ProjectState> for String {
+    fn from(state: ProjectState) -> String {
+        match state {
+            ProjectState::Active => "active".to_string(),
+            ProjectState::Paused => "paused".to_string(),
+            ProjectState::Finished => "finished".to_string(),
+            ProjectState::Unknown(state) => state,
+        }
+    }
+}
+
+impl From<String> for ProjectState {
+    fn from(state: String) -> ProjectState {
+        match state.as_ref() {
+            "active" => ProjectState::Active,
+            "paused" => ProjectState::Paused,
+            "finished" => ProjectState::Finished,
+            _ => ProjectState::Unknown(state),
+        }
+    }
+}
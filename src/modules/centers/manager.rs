@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Strand, Thing};
+use surrealdb::{Notification, Surreal};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, OnDelete};
+use crate::connection::{self, Session, Subscription, Tenant};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::health;
+use crate::ident::Ident;
+use crate::models::center::Center;
+use crate::modules::projects::migrations;
+use crate::shutdown::Shutdown;
+
+/// Event on the centers table that keeps or deletes the projects of a
+/// deleted center, depending on `centers.on_delete`.
+const DELETE_EVENT: &str = "center_projects";
+
+/// The part of `INFO FOR NS` needed to move a namespace.
+#[derive(Deserialize)]
+struct NsInfo {
+    #[serde(default)]
+    databases: BTreeMap<String, String>,
+}
+
+/// The part of `INFO FOR KV` needed to tell whether a namespace is taken.
+#[derive(Deserialize)]
+struct KvInfo {
+    #[serde(default)]
+    namespaces: BTreeMap<String, String>,
+}
+
+/// Gives every center its namespace, named after the center, and moves the
+/// namespace along when the center is renamed.
+pub struct CentersManager {
+    db: Surreal<Any>,
+    main: Session,
+    config: Arc<Config>,
+    listeners: Vec<Arc<dyn CentersManagerTrait>>,
+    /// Name of every center by id, to tell a rename from another update.
+    known: Mutex<HashMap<String, String>>,
+    shutdown: Shutdown,
+}
+
+impl CentersManager {
+    pub async fn new(config: Arc<Config>, shutdown: Shutdown) -> Self {
+        let db = connection::connect(&config).await;
+        let main = Session::new(&db, &config.namespaces.global, &config.namespaces.main);
+
+        Self {
+            db,
+            main,
+            config,
+            listeners: Vec::new(),
+            known: Mutex::new(HashMap::new()),
+            shutdown,
+        }
+    }
+
+    /// Tells `listener` when the namespace of a center moves.
+    pub fn listen(&mut self, listener: Arc<dyn CentersManagerTrait>) {
+        self.listeners.push(listener);
+    }
+
+    #[tracing::instrument(name = "centers", skip_all, fields(table = %self.config.tables.centers))]
+    pub async fn start(&self) -> Result<()> {
+        let health = health::register("centers");
+
+        if self.config.centers.editor_pass.is_empty() {
+            warn!("No centers.editor_pass, the center namespaces get no editor user");
+        }
+
+        self.guard_deletion().await?;
+        self.init_existing().await?;
+
+        let main = Tenant::root(
+            &self.db,
+            &self.config.namespaces.global,
+            &self.config.namespaces.main,
+        );
+        let subscription = Subscription {
+            table: &self.config.tables.centers,
+            manager: "centers",
+            center: "",
+            project: "",
+        };
+
+        connection::supervise(
+            &main,
+            &self.config,
+            subscription,
+            &health,
+            &self.shutdown,
+            |notification| self.handle_actions(notification),
+            || self.reconcile(),
+        )
+        .await;
+
+        health.retire();
+
+        Ok(())
+    }
+
+    /// Deleting a center with projects either fails or deletes the projects
+    /// in the same transaction, the live query only sees it afterwards.
+    async fn guard_deletion(&self) -> Result<()> {
+        let projects = &self.config.tables.projects;
+        let then = match self.config.centers.on_delete {
+            OnDelete::Block => format!(
+                r#"IF count((SELECT id FROM {projects} WHERE center = $before.id)) > 0 {{
+                    THROW "center " + $before.name + " still has projects";
+                }};"#
+            ),
+            OnDelete::Cascade => format!("DELETE {projects} WHERE center = $before.id;"),
+        };
+
+        let sql = format!(
+            r#"DEFINE EVENT {DELETE_EVENT} ON TABLE {} WHEN $event = "DELETE" THEN {{ {then} }};"#,
+            self.config.tables.centers
+        );
+
+        self.main.execute(&sql).await.query(&sql)
+    }
+
+    async fn init_existing(&self) -> Result<()> {
+        for center in self.select_centers().await? {
+            self.remember(&center);
+
+            if let Err(error) = self.define(&center.name).await {
+                error!(%error, center = %center.name, "Failed to define the namespace");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Catches up with centers created, renamed or deleted while the live
+    /// query was down.
+    async fn reconcile(&self) -> Result<()> {
+        let centers = self.select_centers().await?;
+        let known = self.known.lock().unwrap().clone();
+
+        let current: HashSet<String> = centers
+            .iter()
+            .filter_map(|c| c.id.as_ref().map(Thing::to_string))
+            .collect();
+
+        for center in &centers {
+            let Some(id) = &center.id else {
+                continue;
+            };
+
+            let handled = match known.get(&id.to_string()) {
+                None => self.on_create(center).await,
+                Some(name) if *name != center.name => self.on_update(center).await,
+                Some(_) => Ok(()),
+            };
+
+            if let Err(error) = handled {
+                error!(%error);
+            }
+        }
+
+        for (id, name) in known.into_iter().filter(|(id, _)| !current.contains(id)) {
+            self.known.lock().unwrap().remove(&id);
+
+            if let Err(error) = self.on_delete(&name).await {
+                error!(%error);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remember(&self, center: &Center) {
+        if let Some(id) = &center.id {
+            self.known
+                .lock()
+                .unwrap()
+                .insert(id.to_string(), center.name.clone());
+        }
+    }
+
+    async fn select_centers(&self) -> Result<Vec<Center>> {
+        let sql = format!("SELECT * FROM {};", self.config.tables.centers);
+        let mut res = self.main.query(&sql).await.query(&sql)?;
+
+        res.take(res.num_statements() - 1).query(&sql)
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        action = ?notification.action,
+        record = notification.data.id.as_ref().map(tracing::field::display),
+    ))]
+    async fn handle_actions(&self, notification: Notification<Center>) -> Result<()> {
+        let center = notification.data;
+
+        match notification.action {
+            surrealdb::Action::Create => self.on_create(&center).await?,
+            surrealdb::Action::Update => self.on_update(&center).await?,
+            surrealdb::Action::Delete => {
+                let known = center
+                    .id
+                    .as_ref()
+                    .and_then(|id| self.known.lock().unwrap().remove(&id.to_string()));
+
+                self.on_delete(known.as_deref().unwrap_or(&center.name))
+                    .await?;
+            }
+            action => debug!(?action, "Action not supported"),
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(center = %center.name))]
+    async fn on_create(&self, center: &Center) -> Result<()> {
+        Ident::new(center.name.as_str())?;
+        self.remember(center);
+        self.define(&center.name).await?;
+
+        info!("Center created");
+
+        Ok(())
+    }
+
+    /// Moves the namespace when the name changed. The projects of the center
+    /// are detached while their databases are copied, and attached again to
+    /// the old namespace when the move failed.
+    #[tracing::instrument(skip_all, fields(center = %center.name, from))]
+    async fn on_update(&self, center: &Center) -> Result<()> {
+        let id = center
+            .id
+            .as_ref()
+            .ok_or_else(|| SupervisorError::invalid("center without id"))?;
+
+        let Some(old) = self.known.lock().unwrap().get(&id.to_string()).cloned() else {
+            return self.on_create(center).await;
+        };
+        if old == center.name {
+            return Ok(());
+        }
+        tracing::Span::current().record("from", old.as_str());
+        Ident::new(center.name.as_str()).record(id)?;
+
+        for listener in &self.listeners {
+            if let Err(error) = listener.on_center_detach(&old).await {
+                error!(%error);
+            }
+        }
+
+        let moved = self.rename(&old, &center.name).await.record(id);
+        let attached = if moved.is_ok() { &center.name } else { &old };
+
+        for listener in &self.listeners {
+            if let Err(error) = listener.on_center_attach(&old, attached).await {
+                error!(%error);
+            }
+        }
+
+        moved?;
+        self.remember(center);
+
+        info!("Center renamed");
+
+        Ok(())
+    }
+
+    /// The event already dealt with the projects, the namespace stays for
+    /// their backups and only loses its editor.
+    #[tracing::instrument(skip_all, fields(center = %center))]
+    async fn on_delete(&self, center: &str) -> Result<()> {
+        if !self.config.centers.editor_pass.is_empty() {
+            let sql = format!(
+                "REMOVE USER {} ON NAMESPACE;",
+                Ident::from(&self.config.centers.editor)
+            );
+
+            self.namespace(center).execute(&sql).await.query(&sql)?;
+        }
+
+        info!("Center deleted");
+
+        Ok(())
+    }
+
+    /// Defines the namespace of `center` and its editor, both are redefined
+    /// as they are so a changed password applies on the next start.
+    async fn define(&self, center: &str) -> Result<()> {
+        let sql = format!("DEFINE NAMESPACE {};", Ident::from(center));
+        self.main.execute(&sql).await.query(&sql)?;
+
+        let centers = &self.config.centers;
+        if centers.editor_pass.is_empty() {
+            return Ok(());
+        }
+
+        let editor = Ident::from(&centers.editor);
+        let sql = format!(
+            "DEFINE USER {editor} ON NAMESPACE PASSWORD {} ROLES EDITOR;",
+            Strand::from(centers.editor_pass.as_str())
+        );
+
+        // the password stays out of the error
+        self.namespace(center)
+            .execute(&sql)
+            .await
+            .query(&format!("DEFINE USER {editor} ON NAMESPACE"))
+    }
+
+    /// Copies every database of `old` into `new`, then removes `old`. A name
+    /// another namespace already has is refused, the two would mix, and a
+    /// copy that fails removes `new` again.
+    async fn rename(&self, old: &str, new: &str) -> Result<()> {
+        let mut res = self.main.query("INFO FOR KV;").await?;
+        let kv: Option<KvInfo> = res.take(res.num_statements() - 1)?;
+        // INFO escapes the names it lists
+        if kv.is_some_and(|kv| kv.namespaces.contains_key(&Ident::from(new).to_string())) {
+            return Err(SupervisorError::invalid(format!(
+                "namespace {new} already exists"
+            )));
+        }
+
+        let mut res = self.namespace(old).query("INFO FOR NS;").await?;
+        let info: Option<NsInfo> = res.take(res.num_statements() - 1)?;
+        let databases = info.map(|info| info.databases).unwrap_or_default();
+
+        let copied = async {
+            self.define(new).await?;
+
+            let db = connection::exclusive(&self.config).await?;
+            for database in databases.keys().map(|key| Ident::from_info(key)) {
+                let database = database.as_str();
+                let buffer = migrations::dump(&db, old, database)
+                    .await
+                    .tenant(old, database)?;
+
+                migrations::import(&db, new, database, &buffer)
+                    .await
+                    .tenant(new, database)?;
+
+                debug!(%database, "Database moved");
+            }
+
+            Ok::<_, SupervisorError>(())
+        }
+        .await;
+
+        // a retry would take the half built namespace for another center's
+        if let Err(error) = copied {
+            let sql = format!("REMOVE NAMESPACE {};", Ident::from(new));
+            if let Err(error) = self.main.execute(&sql).await {
+                error!(%error, "Failed to remove the half built namespace");
+            }
+
+            return Err(error);
+        }
+
+        let sql = format!("REMOVE NAMESPACE {};", Ident::from(old));
+        self.main.execute(&sql).await.query(&sql)?;
+
+        Ok(())
+    }
+
+    /// Statements on the namespace of `center`, SurrealDB still wants a
+    /// database selected for some of them.
+    fn namespace(&self, center: &str) -> Session {
+        Session::new(&self.db, center, &self.config.namespaces.main)
+    }
+}
+
+/// Managers holding on to the projects of a center.
+#[async_trait::async_trait]
+pub trait CentersManagerTrait: Send + Sync + 'static {
+    /// Stops touching the databases of `center`, they are about to move.
+    async fn on_center_detach(&self, center: &str) -> Result<()>;
+    /// The databases of `old` are now in `new`, which is `old` again when
+    /// the move failed.
+    async fn on_center_attach(&self, old: &str, new: &str) -> Result<()>;
+}
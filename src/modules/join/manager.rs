@@ -1,190 +1,392 @@
-use futures::stream::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
 use serde_json::Value;
-use surrealdb::engine::any::{self, Any};
-use surrealdb::opt::auth::Root;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::Thing;
 use surrealdb::{Notification, Surreal};
+use tracing::{debug, error, info, warn};
 
+use crate::config::Config;
+use crate::connection::{self, Session, Subscription, Tenant};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::ident::Ident;
 use crate::models::join::Join;
-use crate::models::user::{IntervUser, IntervUserPrev};
-use crate::modules::projects::manager::Credentials;
+use crate::models::user::IntervUserPrev;
+use crate::modules::join::sync::StateSync;
+use crate::shutdown::Shutdown;
+use crate::{health, metrics};
+
+/// A join with its project and the role of its user in the center, the
+/// project is gone when `center` and `name` are missing.
+#[derive(Debug, Deserialize)]
+struct JoinRow {
+    id: Thing,
+    user: Thing,
+    state: Option<String>,
+    center: Option<String>,
+    name: Option<String>,
+    role: Option<String>,
+}
+
+/// A user pointing at a project it has no join with.
+#[derive(Debug, Deserialize)]
+struct ProjectRow {
+    center: Option<String>,
+    name: String,
+}
 
 pub struct JoinManager {
     db: Surreal<Any>,
-    cred: Credentials,
+    main: Session,
+    config: Arc<Config>,
+    sync: StateSync,
+    shutdown: Shutdown,
 }
 
 impl JoinManager {
-    pub async fn new(url: impl Into<String>, cred: Credentials) -> Self {
-        let url: String = url.into();
+    pub async fn new(config: Arc<Config>, shutdown: Shutdown) -> Self {
+        let db = connection::connect(&config).await;
+        let main = Session::new(&db, &config.namespaces.global, &config.namespaces.main);
 
-        let db = any::connect(format!("ws://{}", url))
-            .await
-            .expect("Failed to connect to database");
+        Self {
+            sync: StateSync::new(&db, &config),
+            db,
+            main,
+            config,
+            shutdown,
+        }
+    }
+
+    #[tracing::instrument(name = "join", skip_all, fields(table = %self.config.tables.join))]
+    pub async fn start(&self) -> Result<()> {
+        // joins created while the supervisor was down
+        if let Err(error) = self.reconcile().await {
+            metrics::failure("join", "", "");
+            error!(%error);
+        }
+
+        tokio::join!(self.listen(), self.reconcile_periodically());
 
-        let foo = Root {
-            username: cred.user.as_str(),
-            password: cred.pass.as_str(),
+        Ok(())
+    }
+
+    async fn listen(&self) {
+        let health = health::register("join");
+        let main = Tenant::root(
+            &self.db,
+            &self.config.namespaces.global,
+            &self.config.namespaces.main,
+        );
+        let subscription = Subscription {
+            table: &self.config.tables.join,
+            manager: "join",
+            center: "",
+            project: "",
         };
 
-        db.signin(foo).await.expect("Failed to signin");
+        connection::supervise(
+            &main,
+            &self.config,
+            subscription,
+            &health,
+            &self.shutdown,
+            |notification| self.handle_actions(notification),
+            || self.reconcile(),
+        )
+        .await;
 
-        db.use_ns("global")
-            .use_db("main")
-            .await
-            .expect("Failed to use ns and db");
+        health.retire();
+    }
 
-        Self { db, cred }
+    /// Runs [`JoinManager::reconcile`] every `joins.reconcile_interval`
+    /// seconds, for whatever the live queries missed in between.
+    async fn reconcile_periodically(&self) {
+        let every = self.config.joins.reconcile_interval;
+        if every == 0 {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(every)) => {}
+                _ = self.shutdown.triggered() => return,
+            }
+
+            if let Err(error) = self.reconcile().await {
+                metrics::failure("join", "", "");
+                error!(%error);
+            }
+        }
     }
 
-    pub async fn start(&self) -> Result<(), &str> {
-        // self.init_existing()
-        //     .await
-        //     .map_err(|_| "Failed to init existing projects")?;
+    /// Compares the joins with the users of every project. A participant
+    /// without a project user gets one, a state that was not synced is, and
+    /// project users nobody joined are only reported, they may have been
+    /// added by hand.
+    async fn reconcile(&self) -> Result<()> {
+        let sql = format!(
+            "SELECT name, center.name AS center FROM {};",
+            self.config.tables.projects
+        );
+        let mut res = self.main.query(&sql).await.query(&sql)?;
+        let projects: Vec<ProjectRow> = res.take(res.num_statements() - 1).query(&sql)?;
 
-        let mut stream = self
-            .db
-            .select("join")
-            .live()
-            .await
-            .map_err(|_| "Failed to get projects")?;
-
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(notification) => {
-                    if let Err(error) = self.handle_actions(notification).await {
-                        eprintln!("{error}");
-                    }
-                }
+        let sql = self.select_joins(&self.config.tables.join);
+        let mut res = self.main.query(&sql).await.query(&sql)?;
+        let joins: Vec<JoinRow> = res.take(res.num_statements() - 1).query(&sql)?;
+
+        for join in joins.iter().filter(|join| join.name.is_none()) {
+            warn!(record = %join.id, user = %join.user, "Join of a deleted project");
+        }
+
+        for ProjectRow { center, name } in projects {
+            let Some(center) = center else {
+                continue;
+            };
+
+            let joins: Vec<&JoinRow> = joins
+                .iter()
+                .filter(|join| join.name.as_ref() == Some(&name))
+                .filter(|join| join.center.as_ref() == Some(&center))
+                .collect();
 
-                Err(error) => eprintln!("{error}"),
+            // keep going, the next pass retries the ones that failed
+            if let Err(error) = self.reconcile_project(&center, &name, &joins).await {
+                metrics::failure("join", &center, &name);
+                error!(%error);
             }
         }
 
         Ok(())
     }
 
-    async fn handle_actions(&self, notification: Notification<Join>) -> Result<(), &str> {
-        let join = notification.data;
+    async fn reconcile_project(&self, center: &str, name: &str, joins: &[&JoinRow]) -> Result<()> {
+        let sql = format!("SELECT VALUE id FROM {};", self.config.tables.users);
+        let mut res = connection::tenant(&self.db, &self.config, center, name)
+            .await
+            .tenant(center, name)?
+            .session()
+            .query(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, name)?;
+        let users: Vec<Thing> = res
+            .take(res.num_statements() - 1)
+            .query(&sql)
+            .tenant(center, name)?;
 
-        match notification.action {
-            surrealdb::Action::Create => {
-                // println!("Join created: {}", join.id);
-
-                let mut res = self
-                    .db
-                    .query(
-                        r#"
-                        SELECT
-                            out.center.name as center,
-                            out.name as name,
-                            (<-users->roled[WHERE out IS $parent.out.center].role)[0] AS role
-                            FROM ONLY $b_id;
-                        "#,
-                    )
-                    .bind(("b_id", &join.id))
+        for join in joins {
+            let repaired = if users.contains(&join.user) {
+                self.sync
+                    .settle(center, name, &join.user)
                     .await
-                    .unwrap();
-
-                let center_name_role: (String, String, String) = res
-                    .take(res.num_statements() - 1)
-                    .map(|row: Option<Value>| {
-                        let row = row.unwrap();
-
-                        let center = row["center"].to_string().trim_matches('"').to_string();
-                        let name = row["name"].to_string().trim_matches('"').to_string();
-                        let role = row["role"].to_string().trim_matches('"').to_string();
-
-                        (center, name, role)
-                    })
-                    .map_err(|e| {
-                        eprintln!("Failed to get interv_user: {}", e);
-                        return "Error: interv_user";
-                    })?;
-
-                let center = center_name_role.0;
-                let name = center_name_role.1;
-                let role = center_name_role.2;
-
-                match role.as_str() {
-                    "parti" | "guest" => {}
-                    _ => return Ok(()),
-                }
+                    .map(|synced| synced.then_some("state"))
+            } else if self.is_participant(join) {
+                self.admit(join, center, name).await.map(|()| Some("user"))
+            } else {
+                Ok(None)
+            };
 
-                let sql = format!(
-                    r#"
-                    USE NS {} DB {};
-                    CREATE $b_user_id SET role = $b_role;"#,
-                    &center, &name
-                );
-
-                let query = self
-                    .db
-                    .query(sql)
-                    .bind(("b_user_id", &join.user))
-                    .bind(("b_role", &role));
-
-                match query.await {
-                    Ok(mut res) => {
-                        let inter_user: Result<IntervUser, ()> = res
-                            .take(res.num_statements() - 1)
-                            .map(|row: Option<IntervUserPrev>| {
-                                let row = row.unwrap();
-
-                                IntervUser {
-                                    id: row.id,
-                                    role: row.role,
-                                    state: row.state.into(),
-                                }
-                            })
-                            .map_err(|e| {
-                                eprintln!("Failed to get interv_user: {}", e);
-                                return ();
-                            });
-
-                        if let Err(_) = inter_user {
-                            eprintln!("Failed to get interv_user");
-                            return Ok(());
-                        }
-                        let inter_user = inter_user.unwrap();
-
-                        let state: String = inter_user.state.into();
-
-                        self.db
-                            .query(
-                                r#"
-                                UPDATE $b_join_id SET state = $b_state;
-                                "#,
-                            )
-                            .bind(("b_join_id", &join.id))
-                            .bind(("b_state", &state))
-                            .await
-                            .unwrap();
-
-                        println!("User {} join project {} \n", join.user, join.project,);
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to create interv_user: {}", e);
-                    }
+            match repaired {
+                Ok(Some(kind)) => {
+                    metrics::JOIN_REPAIRS
+                        .with_label_values(&[center, name, kind])
+                        .inc();
+                    info!(%center, project = %name, record = %join.id, %kind, "Join repaired");
                 }
+                Ok(None) => {}
+                Err(error) => error!(%error),
             }
-            surrealdb::Action::Update => {
-                // println!("Join updated: {}", join.id);
-
-                // done by an event
-                // if let Some(state) = join.state {
-                //     if state == "completed" {
-                //         self.db
-                //             .query(r#"UPDATE $b_user_id SET project = NONE;"#)
-                //             .bind(("b_user_id", &join.user))
-                //             .await
-                //             .unwrap();
-                //     }
-                // };
+        }
+
+        let unjoined: Vec<&Thing> = users
+            .iter()
+            .filter(|user| !joins.iter().any(|join| join.user == **user))
+            .collect();
+        for user in &unjoined {
+            warn!(%center, project = %name, %user, "Project user without a join");
+        }
+
+        metrics::UNJOINED_USERS
+            .with_label_values(&[center, name])
+            .set(unjoined.len() as i64);
+
+        Ok(())
+    }
+
+    /// Joins with their project and the role of their user in its center,
+    /// selected from `what`.
+    fn select_joins(&self, what: &str) -> String {
+        format!(
+            r#"
+            SELECT
+                id,
+                in AS user,
+                state,
+                out.center.name AS center,
+                out.name AS name,
+                (<-{}->roled[WHERE out IS $parent.out.center].role)[0] AS role
+                FROM {what};
+            "#,
+            self.config.tables.users
+        )
+    }
+
+    /// Users without a role in the center are not participants either.
+    fn is_participant(&self, join: &JoinRow) -> bool {
+        join.role
+            .as_ref()
+            .is_some_and(|role| self.config.roles.participants.contains(role))
+    }
+
+    #[tracing::instrument(skip_all, fields(action = ?notification.action, record = %notification.data.id))]
+    async fn handle_actions(&self, notification: Notification<Join>) -> Result<()> {
+        let join = notification.data;
+
+        match notification.action {
+            surrealdb::Action::Create => self.on_create(&join).await?,
+            surrealdb::Action::Update => self.on_update(&join).await?,
+            surrealdb::Action::Delete => self.on_delete(&join).await?,
+            action => debug!(?action, "Action not supported"),
+        }
+
+        Ok(())
+    }
+
+    async fn on_create(&self, join: &Join) -> Result<()> {
+        let sql = self.select_joins("ONLY $b_id");
+        let mut res = self
+            .main
+            .query(&sql)
+            .bind(("b_id", &join.id))
+            .await
+            .record(&join.id)?;
+
+        let row: Option<JoinRow> = res.take(res.num_statements() - 1).record(&join.id)?;
+        let row = row.ok_or_else(|| SupervisorError::not_found("join").record(&join.id))?;
+
+        let (Some(center), Some(name)) = (&row.center, &row.name) else {
+            return Err(SupervisorError::invalid("join without project").record(&join.id));
+        };
+
+        if !self.is_participant(&row) {
+            return Ok(());
+        }
+
+        self.admit(&row, center, name).await
+    }
+
+    /// Creates the project user of a participant, in the state of its join
+    /// when it already has one.
+    async fn admit(&self, join: &JoinRow, center: &str, name: &str) -> Result<()> {
+        let sql = match join.state {
+            Some(_) => "CREATE $b_user_id SET role = $b_role, state = $b_state;",
+            None => "CREATE $b_user_id SET role = $b_role;",
+        };
+
+        let mut res = connection::tenant(&self.db, &self.config, center, name)
+            .await
+            .tenant(center, name)?
+            .session()
+            .query(sql)
+            .bind(("b_user_id", &join.user))
+            .bind(("b_role", &join.role))
+            .bind(("b_state", &join.state))
+            .await
+            .record(&join.user)
+            .tenant(center, name)?;
+
+        let row: Option<IntervUserPrev> = res
+            .take(res.num_statements() - 1)
+            .record(&join.user)
+            .tenant(center, name)?;
+        if row.is_none() {
+            return Err(SupervisorError::not_found("interv_user")
+                .record(&join.user)
+                .tenant(center, name));
+        }
+
+        // the new project user is the edit, its state goes to the join
+        self.sync.settle(center, name, &join.user).await?;
+
+        info!(%center, project = %name, user = %join.user, "User joined project");
+
+        Ok(())
+    }
+
+    /// An admin changed the join, its state goes to the project user.
+    async fn on_update(&self, join: &Join) -> Result<()> {
+        let Some((center, name)) = self.tenant_of(&join.project).await? else {
+            return Ok(());
+        };
+
+        self.sync.settle(&center, &name, &join.user).await?;
+
+        Ok(())
+    }
+
+    /// Takes the participant out of the project: its project user goes and
+    /// the project of the user is cleared if it is still this one, both in
+    /// one transaction so a failure leaves neither done.
+    async fn on_delete(&self, join: &Join) -> Result<()> {
+        // a deleted project took its users along
+        let tenant = self.tenant_of(&join.project).await?;
+
+        let leave = match &tenant {
+            Some((center, name)) => format!(
+                "USE NS {} DB {}; DELETE $b_user_id; USE NS {} DB {};",
+                Ident::from(center),
+                Ident::from(name),
+                Ident::from(&self.config.namespaces.global),
+                Ident::from(&self.config.namespaces.main),
+            ),
+            None => String::new(),
+        };
+        let sql = format!(
+            r#"
+            BEGIN TRANSACTION;
+            {leave}
+            UPDATE $b_user_id SET project = NONE WHERE project IS $b_project;
+            COMMIT TRANSACTION;
+            "#
+        );
+        self.main
+            .query(&sql)
+            .bind(("b_user_id", &join.user))
+            .bind(("b_project", &join.project))
+            .await
+            .record(&join.user)?
+            .check()
+            .record(&join.user)?;
+
+        match tenant {
+            Some((center, name)) => {
+                info!(center = %center, project = %name, user = %join.user, "User left project")
             }
-            surrealdb::Action::Delete => { /* println!("Join deleted: {}", join.id) */ }
-            _ => println!("Action not supported"),
+            None => info!(project = %join.project, user = %join.user, "User left deleted project"),
         }
 
         Ok(())
     }
+
+    /// Center and name of `project`, `None` once it is deleted.
+    async fn tenant_of(&self, project: &Thing) -> Result<Option<(String, String)>> {
+        let sql = "SELECT center.name AS center, name FROM ONLY $b_project;";
+        let mut res = self
+            .main
+            .query(sql)
+            .bind(("b_project", project))
+            .await
+            .record(project)?;
+        let row: Option<Value> = res.take(res.num_statements() - 1).record(project)?;
+
+        Ok(row.and_then(|row| {
+            Some((
+                row["center"].as_str()?.to_string(),
+                row["name"].as_str()?.to_string(),
+            ))
+        }))
+    }
 }
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::{Thing, Value};
+use surrealdb::Surreal;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::connection::{self, Session};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::metrics;
+use crate::models::user::UserState;
+
+/// Held per `center/project/user` while that participant is settled, the
+/// join and the project managers would otherwise read and write the same
+/// pair at once. Other participants settle meanwhile.
+static SETTLING: Lazy<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    Lazy::new(Default::default);
+
+/// The state of a participant on either side, with the markers the
+/// supervisor writes next to it.
+///
+/// `state_synced` is the state as the supervisor last wrote it, so a `state`
+/// that differs from it was edited since. `state_version` grows with every
+/// sync and `state_origin` tells which side the state came from.
+#[derive(Debug, Default, Deserialize)]
+struct Marked {
+    id: Option<Thing>,
+    #[serde(rename = "out")]
+    project: Option<Thing>,
+    state: Option<String>,
+    state_synced: Option<String>,
+    state_version: Option<u64>,
+    state_origin: Option<String>,
+}
+
+impl Marked {
+    fn edited(&self) -> Option<&str> {
+        self.state
+            .as_deref()
+            .filter(|state| Some(*state) != self.state_synced.as_deref())
+    }
+
+    fn version(&self) -> u64 {
+        self.state_version.unwrap_or(0)
+    }
+}
+
+/// Where a synced state was edited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Origin {
+    Project,
+    Join,
+}
+
+impl Origin {
+    fn parse(origin: &str) -> Option<Self> {
+        match origin {
+            "project" => Some(Origin::Project),
+            "join" => Some(Origin::Join),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Origin::Project => "project",
+            Origin::Join => "join",
+        }
+    }
+}
+
+/// Keeps `users.state` of a project and `state` of the matching join equal,
+/// whichever of them was edited.
+///
+/// Both sides are read again on every call instead of trusting the
+/// notification, so the echo of a write made here finds nothing to do and
+/// the two live queries never bounce a state between them. When both sides
+/// were edited before either was synced, the state furthest along wins
+/// (`completed`, `exited`, `standby`, then `active`), no matter which
+/// notification comes first.
+#[derive(Clone)]
+pub struct StateSync {
+    db: Surreal<Any>,
+    main: Session,
+    config: Arc<Config>,
+}
+
+impl StateSync {
+    pub fn new(db: &Surreal<Any>, config: &Arc<Config>) -> Self {
+        let main = Session::new(db, &config.namespaces.global, &config.namespaces.main);
+
+        Self {
+            db: db.clone(),
+            main,
+            config: config.clone(),
+        }
+    }
+
+    /// Brings both sides of `user` in `center/project` to the same state,
+    /// `true` when either of them had to be written.
+    pub async fn settle(&self, center: &str, project: &str, user: &Thing) -> Result<bool> {
+        let key = format!("{center}/{project}/{user}");
+        let lock = SETTLING
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let settled = {
+            let _settling = lock.lock().await;
+            self.sync(center, project, user).await
+        };
+
+        // the last one out removes the lock
+        drop(lock);
+        let mut settling = SETTLING.lock().unwrap();
+        if settling
+            .get(&key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            settling.remove(&key);
+        }
+
+        settled
+    }
+
+    /// The join is written before the project user, so a sync that stops
+    /// half way leaves the join ahead by `state_version` and the next one
+    /// finishes it.
+    async fn sync(&self, center: &str, project: &str, user: &Thing) -> Result<bool> {
+        let tables = &self.config.tables;
+
+        let tenant = connection::tenant(&self.db, &self.config, center, project)
+            .await
+            .tenant(center, project)?
+            .session();
+
+        let mut res = tenant
+            .query("SELECT state, state_synced, state_version, state_origin FROM ONLY $b_user;")
+            .bind(("b_user", user))
+            .await
+            .record(user)
+            .tenant(center, project)?;
+        let in_project: Option<Marked> = res
+            .take(res.num_statements() - 1)
+            .record(user)
+            .tenant(center, project)?;
+
+        let sql = format!(
+            r#"
+            SELECT id, out, state, state_synced, state_version, state_origin FROM ONLY {}
+                WHERE in IS $b_user AND out.name = $b_project AND out.center.name = $b_center
+                LIMIT 1;
+            "#,
+            tables.join
+        );
+        let mut res = self
+            .main
+            .query(&sql)
+            .bind(("b_user", user))
+            .bind(("b_project", project))
+            .bind(("b_center", center))
+            .await
+            .record(user)
+            .tenant(center, project)?;
+        let in_join: Option<Marked> = res
+            .take(res.num_statements() - 1)
+            .record(user)
+            .tenant(center, project)?;
+
+        // a side without the participant has nothing to sync with
+        let (Some(in_project), Some(in_join)) = (in_project, in_join) else {
+            return Ok(false);
+        };
+        let (Some(join), Some(project_id)) = (&in_join.id, &in_join.project) else {
+            return Ok(false);
+        };
+
+        let (state, origin) = match (in_project.edited(), in_join.edited()) {
+            (None, None) => match (in_project.state.as_deref(), in_join.state.as_deref()) {
+                // a sync that stopped half way, the side written first is
+                // ahead and tells where its state came from
+                (Some(ours), Some(theirs)) if ours != theirs => {
+                    let (state, ahead, side) = if in_project.version() > in_join.version() {
+                        (ours, &in_project, Origin::Project)
+                    } else {
+                        (theirs, &in_join, Origin::Join)
+                    };
+                    let origin = ahead.state_origin.as_deref().and_then(Origin::parse);
+
+                    (state, origin.unwrap_or(side))
+                }
+                _ => return Ok(false),
+            },
+            (Some(state), None) => (state, Origin::Project),
+            (None, Some(state)) => (state, Origin::Join),
+            (Some(ours), Some(theirs)) if ours == theirs => (ours, Origin::Project),
+            (Some(ours), Some(theirs)) => {
+                metrics::STATE_CONFLICTS
+                    .with_label_values(&[center, project])
+                    .inc();
+
+                let winner = if rank(ours) >= rank(theirs) {
+                    (ours, Origin::Project)
+                } else {
+                    (theirs, Origin::Join)
+                };
+                warn!(%user, project = ours, join = theirs, kept = winner.0, "State edited on both sides");
+
+                winner
+            }
+        };
+        let state = state.to_string();
+        let version = in_project.version().max(in_join.version()) + 1;
+
+        // a participant who is done takes the last score along and is free
+        // for another project
+        let done = matches!(
+            UserState::from(state.clone()),
+            UserState::Completed | UserState::Exited
+        );
+        let score = if done {
+            let sql = format!(
+                r#"
+                SELECT VALUE score FROM ONLY (
+                    SELECT created, score FROM ONLY {} WHERE user IS $b_user ORDER BY created DESC LIMIT 1
+                ) LIMIT 1;
+                "#,
+                tables.scores
+            );
+            let mut res = tenant
+                .query(&sql)
+                .bind(("b_user", user))
+                .await
+                .record(user)
+                .tenant(center, project)?;
+
+            res.take(res.num_statements() - 1)
+                .record(user)
+                .tenant(center, project)?
+        } else {
+            Value::None
+        };
+
+        // both writes only go through while the state is still the one read
+        // above, an edit made meanwhile comes with its own notification
+        let sql = r#"
+            BEGIN TRANSACTION;
+                LET $joined = (UPDATE $b_join SET
+                    state = $b_state,
+                    state_synced = $b_state,
+                    state_version = $b_version,
+                    state_origin = $b_origin,
+                    updated = time::now()
+                WHERE state IS $b_read);
+                IF $b_done AND array::len($joined) > 0 {
+                    UPDATE $b_join SET score = $b_score;
+                    UPDATE $b_user SET project = NONE WHERE project IS $b_project;
+                };
+            COMMIT TRANSACTION;
+            RETURN $joined.state_synced;
+            "#;
+        let mut res = self
+            .main
+            .query(sql)
+            .bind(("b_join", join))
+            .bind(("b_user", user))
+            .bind(("b_project", project_id))
+            .bind(("b_read", &in_join.state))
+            .bind(("b_state", &state))
+            .bind(("b_version", version))
+            .bind(("b_origin", origin.as_str()))
+            .bind(("b_done", done))
+            .bind(("b_score", score))
+            .await
+            .record(join)
+            .tenant(center, project)?
+            .check()
+            .record(join)
+            .tenant(center, project)?;
+        let synced: Vec<Option<String>> = res
+            .take(res.num_statements() - 1)
+            .record(join)
+            .tenant(center, project)?;
+        let Some(synced) = synced.into_iter().next() else {
+            return Ok(false);
+        };
+        stored(synced, &state, join).tenant(center, project)?;
+
+        let mut res = tenant
+            .query(
+                r#"
+                UPDATE $b_user SET
+                    state = $b_state,
+                    state_synced = $b_state,
+                    state_version = $b_version,
+                    state_origin = $b_origin
+                WHERE state IS $b_read
+                RETURN VALUE state_synced;
+                "#,
+            )
+            .bind(("b_user", user))
+            .bind(("b_read", &in_project.state))
+            .bind(("b_state", &state))
+            .bind(("b_version", version))
+            .bind(("b_origin", origin.as_str()))
+            .await
+            .record(user)
+            .tenant(center, project)?;
+        let synced: Vec<Option<String>> = res
+            .take(res.num_statements() - 1)
+            .record(user)
+            .tenant(center, project)?;
+        // edited since, the join is ahead and the next sync settles both
+        if let Some(synced) = synced.into_iter().next() {
+            stored(synced, &state, user).tenant(center, project)?;
+        }
+
+        info!(%user, %state, origin = origin.as_str(), version, "State synced");
+
+        Ok(true)
+    }
+}
+
+/// Fails when the marker just written did not stick. A table that drops
+/// `state_synced` makes every sync look like a new edit, and the two live
+/// queries would bounce the state between them for good.
+fn stored(synced: Option<String>, state: &str, id: &Thing) -> Result<()> {
+    if synced.as_deref() == Some(state) {
+        return Ok(());
+    }
+
+    Err(SupervisorError::invalid(format!(
+        "state_synced was not stored, the {} table must define the fields of \
+         migrations/0001_state_markers.surql",
+        id.tb
+    ))
+    .record(id))
+}
+
+/// How far along a participant is, the further state wins a conflict.
+fn rank(state: &str) -> u8 {
+    match UserState::from(state.to_string()) {
+        UserState::Active => 0,
+        UserState::Standby => 1,
+        UserState::Exited => 2,
+        UserState::Completed => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use surrealdb::engine::any;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Synced {
+        state: String,
+        state_version: u64,
+        state_origin: String,
+    }
+
+    async fn synced(session: &Session, sql: &str) -> Synced {
+        let mut res = session.query(sql).await.unwrap();
+        let synced: Option<Synced> = res.take(res.num_statements() - 1).unwrap();
+
+        synced.unwrap()
+    }
+
+    #[tokio::test]
+    async fn conflicting_edits_keep_the_state_further_along() {
+        let db = any::connect("mem://").await.unwrap();
+        let mut config = Config::default();
+        config.db.host = "mem://".to_string();
+        let config = Arc::new(config);
+
+        let main = Session::new(&db, "global", "main");
+        let project = Session::new(&db, "center_sync", "project_sync");
+        main.execute(
+            r#"
+            CREATE centers:center_sync SET name = "center_sync";
+            CREATE projects:project_sync SET name = "project_sync", center = centers:center_sync;
+            CREATE users:dave SET project = projects:project_sync;
+            RELATE users:dave->join->projects:project_sync SET
+                state = "active", state_synced = "active", state_version = 1;
+            "#,
+        )
+        .await
+        .unwrap();
+        project
+            .execute(r#"CREATE users:dave SET state = "active", state_synced = "active", state_version = 1;"#)
+            .await
+            .unwrap();
+
+        // both sides edited before either notification was handled
+        project
+            .execute(r#"UPDATE users:dave SET state = "completed";"#)
+            .await
+            .unwrap();
+        main.execute(r#"UPDATE join SET state = "standby";"#)
+            .await
+            .unwrap();
+
+        let sync = StateSync::new(&db, &config);
+        let user = Thing::from(("users", "dave"));
+        sync.settle("center_sync", "project_sync", &user)
+            .await
+            .unwrap();
+
+        let expected = Synced {
+            state: "completed".to_string(),
+            state_version: 2,
+            state_origin: "project".to_string(),
+        };
+        let in_project = "SELECT * FROM ONLY users:dave;";
+        let in_join = "SELECT * FROM ONLY join LIMIT 1;";
+        assert_eq!(synced(&project, in_project).await, expected);
+        assert_eq!(synced(&main, in_join).await, expected);
+
+        // a finished participant is free for another project
+        let mut res = main
+            .query("RETURN users:dave.project IS NONE;")
+            .await
+            .unwrap();
+        let cleared: Option<bool> = res.take(res.num_statements() - 1).unwrap();
+        assert_eq!(cleared, Some(true));
+
+        // the echo of those writes finds nothing to do
+        sync.settle("center_sync", "project_sync", &user)
+            .await
+            .unwrap();
+        assert_eq!(synced(&main, in_join).await.state_version, 2);
+
+        // an edit of the join reaches the project
+        main.execute(r#"UPDATE join SET state = "active";"#)
+            .await
+            .unwrap();
+        sync.settle("center_sync", "project_sync", &user)
+            .await
+            .unwrap();
+
+        let expected = Synced {
+            state: "active".to_string(),
+            state_version: 3,
+            state_origin: "join".to_string(),
+        };
+        assert_eq!(synced(&project, in_project).await, expected);
+        assert_eq!(synced(&main, in_join).await, expected);
+
+        // a sync that wrote the join and failed on the project is finished
+        main.execute(
+            r#"UPDATE join SET state = "standby", state_synced = "standby", state_version = 4;"#,
+        )
+        .await
+        .unwrap();
+        sync.settle("center_sync", "project_sync", &user)
+            .await
+            .unwrap();
+
+        let expected = Synced {
+            state: "standby".to_string(),
+            state_version: 5,
+            state_origin: "join".to_string(),
+        };
+        assert_eq!(synced(&project, in_project).await, expected);
+        assert_eq!(synced(&main, in_join).await, expected);
+    }
+
+    #[tokio::test]
+    async fn markers_that_are_not_stored_stop_the_sync() {
+        let db = any::connect("mem://").await.unwrap();
+        let mut config = Config::default();
+        config.db.host = "mem://".to_string();
+        let config = Arc::new(config);
+
+        let main = Session::new(&db, "global", "main");
+        let project = Session::new(&db, "center_strict", "project_strict");
+        main.execute(
+            r#"
+            CREATE centers:center_strict SET name = "center_strict";
+            CREATE projects:project_strict SET name = "project_strict", center = centers:center_strict;
+            RELATE users:erin->join->projects:project_strict SET state = "active";
+            "#,
+        )
+        .await
+        .unwrap();
+        // without the fields of the markers migration
+        project
+            .execute(
+                r#"
+                DEFINE TABLE users SCHEMAFULL;
+                DEFINE FIELD state ON users TYPE string;
+                CREATE users:erin SET state = "standby";
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let sync = StateSync::new(&db, &config);
+        let user = Thing::from(("users", "erin"));
+        let error = sync
+            .settle("center_strict", "project_strict", &user)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("state_synced"), "{error}");
+
+        // with them the same users sync
+        let markers = include_str!("../../../migrations/0001_state_markers.surql");
+        project.execute(markers).await.unwrap();
+        sync.settle("center_strict", "project_strict", &user)
+            .await
+            .unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod centers;
 pub mod join;
 pub mod projects;
 // pub mod users;
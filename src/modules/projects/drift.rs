@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+
+use crate::config::Config;
+use crate::connection::Session;
+use crate::error::{Result, ResultExt};
+use crate::models::project::{ProjectState, ProjectWithCenter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Table,
+    Field,
+    Index,
+    Event,
+    Function,
+}
+
+/// Every definition of a database by kind and name, fields, indexes and
+/// events are named `table.name`.
+pub type Schema = BTreeMap<(Kind, String), String>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drift {
+    /// In the template only.
+    Missing,
+    /// In the project only.
+    Extra,
+    /// Defined differently.
+    Changed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub kind: Kind,
+    pub name: String,
+    pub drift: Drift,
+}
+
+#[derive(Deserialize)]
+struct DbInfo {
+    #[serde(default)]
+    tables: BTreeMap<String, String>,
+    #[serde(default)]
+    functions: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct TableInfo {
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+    #[serde(default)]
+    indexes: BTreeMap<String, String>,
+    #[serde(default)]
+    events: BTreeMap<String, String>,
+}
+
+/// Reads the definitions of `ns/database`, only `INFO` statements are run.
+pub async fn schema(db: &Surreal<Any>, ns: &str, database: &str) -> Result<Schema> {
+    let session = Session::new(db, ns, database);
+    let mut schema = Schema::new();
+
+    let info: Option<DbInfo> = fetch_info(&session, "INFO FOR DB;")
+        .await
+        .tenant(ns, database)?;
+    let Some(info) = info else {
+        return Ok(schema);
+    };
+
+    for (name, definition) in info.functions {
+        schema.insert((Kind::Function, format!("fn::{name}")), definition);
+    }
+
+    for (table, definition) in info.tables {
+        let sql = format!("INFO FOR TABLE {table};");
+        let table_info: Option<TableInfo> =
+            fetch_info(&session, &sql).await.tenant(ns, database)?;
+
+        if let Some(table_info) = table_info {
+            let kinds = [
+                (Kind::Field, table_info.fields),
+                (Kind::Index, table_info.indexes),
+                (Kind::Event, table_info.events),
+            ];
+
+            for (kind, definitions) in kinds {
+                for (name, definition) in definitions {
+                    schema.insert((kind, format!("{table}.{name}")), definition);
+                }
+            }
+        }
+
+        schema.insert((Kind::Table, table), definition);
+    }
+
+    Ok(schema)
+}
+
+async fn fetch_info<T>(session: &Session, sql: &str) -> Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let mut res = session.query(sql).await.query(sql)?;
+
+    res.take(res.num_statements() - 1).query(sql)
+}
+
+/// What a project would need to match the template, in kind and name order.
+pub fn diff(template: &Schema, project: &Schema) -> Vec<Difference> {
+    let mut differences: Vec<Difference> = template
+        .iter()
+        .filter_map(|(key, definition)| match project.get(key) {
+            None => Some((key, Drift::Missing)),
+            Some(other) if other != definition => Some((key, Drift::Changed)),
+            Some(_) => None,
+        })
+        .chain(
+            project
+                .keys()
+                .filter(|key| !template.contains_key(*key))
+                .map(|key| (key, Drift::Extra)),
+        )
+        .map(|((kind, name), drift)| Difference {
+            kind: *kind,
+            name: name.clone(),
+            drift,
+        })
+        .collect();
+
+    differences.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    differences
+}
+
+/// Compares every project with the template and writes the differences to
+/// `out`, without changing anything. Returns whether any project drifted.
+///
+/// Finished projects are skipped, they were made read-only on purpose.
+pub async fn report(db: &Surreal<Any>, config: &Config, out: &mut impl Write) -> Result<bool> {
+    let namespaces = &config.namespaces;
+    let template = schema(db, &namespaces.global, &namespaces.template).await?;
+
+    let sql = format!("SELECT * FROM {} FETCH center;", config.tables.projects);
+    let mut res = Session::new(db, &namespaces.global, &namespaces.main)
+        .query(&sql)
+        .await
+        .query(&sql)?;
+    let mut projects: Vec<ProjectWithCenter> = res.take(res.num_statements() - 1).query(&sql)?;
+    projects.sort_by(|a, b| (&a.center.name, &a.name).cmp(&(&b.center.name, &b.name)));
+
+    let mut drifted = false;
+
+    for project in projects {
+        let tenant = format!("{}/{}", project.center.name, project.name);
+
+        if project.state == ProjectState::Finished {
+            writeln!(out, "{tenant}: skipped, finished")?;
+            continue;
+        }
+
+        let differences = diff(
+            &template,
+            &schema(db, &project.center.name, &project.name).await?,
+        );
+        if differences.is_empty() {
+            writeln!(out, "{tenant}: in sync")?;
+            continue;
+        }
+
+        drifted = true;
+        writeln!(out, "{tenant}:")?;
+        for difference in differences {
+            writeln!(out, "  {difference}")?;
+        }
+    }
+
+    Ok(drifted)
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Kind::Table => "table",
+            Kind::Field => "field",
+            Kind::Index => "index",
+            Kind::Event => "event",
+            Kind::Function => "function",
+        };
+
+        f.write_str(kind)
+    }
+}
+
+/// `- table flags` for a missing table, `+` for an extra one and `~` for one
+/// defined differently.
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.drift {
+            Drift::Missing => '-',
+            Drift::Extra => '+',
+            Drift::Changed => '~',
+        };
+
+        write!(f, "{sign} {} {}", self.kind, self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(definitions: &[(Kind, &str, &str)]) -> Schema {
+        definitions
+            .iter()
+            .map(|(kind, name, definition)| ((*kind, name.to_string()), definition.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn differences_are_missing_extra_or_changed() {
+        let template = schema(&[
+            (Kind::Table, "scores", "DEFINE TABLE scores"),
+            (
+                Kind::Field,
+                "scores.value",
+                "DEFINE FIELD value ON scores TYPE int",
+            ),
+            (
+                Kind::Function,
+                "fn::on_cron",
+                "DEFINE FUNCTION fn::on_cron() { v2 }",
+            ),
+        ]);
+        let project = schema(&[
+            (Kind::Table, "scores", "DEFINE TABLE scores"),
+            (
+                Kind::Index,
+                "scores.by_user",
+                "DEFINE INDEX by_user ON scores",
+            ),
+            (
+                Kind::Function,
+                "fn::on_cron",
+                "DEFINE FUNCTION fn::on_cron() { v1 }",
+            ),
+        ]);
+
+        let report: Vec<String> = diff(&template, &project)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            report,
+            [
+                "- field scores.value",
+                "+ index scores.by_user",
+                "~ function fn::on_cron",
+            ]
+        );
+        assert!(diff(&template, &template).is_empty());
+    }
+}
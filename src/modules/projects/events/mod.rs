@@ -1,46 +1,66 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use futures::stream::StreamExt;
-use surrealdb::engine::any::{self, Any};
-use surrealdb::opt::auth::Root;
+use surrealdb::engine::any::Any;
 use surrealdb::sql::Thing;
 use surrealdb::{Notification, Surreal};
-use tokio::sync::Mutex;
-use tokio_cron_scheduler::{Job, JobScheduler, JobSchedulerError};
-
+use tokio::sync::{Mutex, OwnedRwLockWriteGuard, RwLock};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use tracing::{error, info, info_span, Instrument};
+
+use crate::config::Config;
+use crate::connection::{self, Session, Subscription};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::models::project::{Project, ProjectState};
 use crate::modules::projects::manager::ProjectsManagerTrait;
+use crate::shutdown::Shutdown;
+use crate::{health, metrics};
 
 use crate::models::event::Event;
 
-use super::manager::Credentials;
+/// The event a cron job belongs to, so it can be released on shutdown or
+/// when its project is deleted.
+struct ScheduledJob {
+    center: String,
+    project: String,
+    event: Thing,
+}
 
 #[derive(Clone)]
 pub struct EventsManager {
     db: Surreal<Any>,
-    cred: Credentials,
+    config: Arc<Config>,
     sched: Arc<Mutex<JobScheduler>>,
+    jobs: Arc<Mutex<HashMap<uuid::Uuid, ScheduledJob>>>,
+    /// Stops the live stream of each `center/project`.
+    streams: Arc<std::sync::Mutex<HashMap<String, Shutdown>>>,
+    /// `center/project` of the paused or finished projects, their events are
+    /// left unscheduled.
+    suspended: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Held shared by the cron executions of each `center/project`, pausing
+    /// or finishing takes it exclusively to wait for the ones in flight.
+    runs: Arc<std::sync::Mutex<HashMap<String, Arc<RwLock<()>>>>>,
+    shutdown: Shutdown,
 }
 
 impl EventsManager {
-    pub async fn new(url: impl Into<String>, cred: Credentials) -> Self {
-        let sched = JobScheduler::new().await.unwrap();
-        sched.start().await.unwrap();
-
-        let db = any::connect(format!("ws://{}", url.into()))
+    pub async fn new(config: Arc<Config>, shutdown: Shutdown) -> Self {
+        let sched = JobScheduler::new()
             .await
-            .expect("Failed to connect to database");
-
-        let foo = Root {
-            username: cred.user.as_str(),
-            password: cred.pass.as_str(),
-        };
+            .expect("Failed to create the scheduler");
+        sched.start().await.expect("Failed to start the scheduler");
 
-        db.signin(foo).await.expect("Failed to signin");
+        let db = connection::connect(&config).await;
 
         Self {
             db,
-            cred,
+            config,
             sched: Arc::new(Mutex::new(sched)),
+            jobs: Arc::default(),
+            streams: Arc::default(),
+            suspended: Arc::default(),
+            runs: Arc::default(),
+            shutdown,
         }
     }
 
@@ -48,93 +68,313 @@ impl EventsManager {
         let manager = self.clone();
         let project = project.into();
         let center = center.into();
+        let span = info_span!(
+            "events",
+            center = %center,
+            project = %project,
+            table = %self.config.tables.events,
+        );
 
-        tokio::spawn(async move {
-            let manager = manager.clone();
+        let stop = self.shutdown.child();
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(format!("{center}/{project}"), stop.clone());
+
+        let health = health::register(format!("events:{center}/{project}"));
+
+        self.shutdown.spawn(
+            async move {
+                let connected = connection::tenant_until(
+                    &manager.db,
+                    &manager.config,
+                    &center,
+                    &project,
+                    &stop,
+                )
+                .await;
+                let Some(tenant) = connected else {
+                    health.retire();
+                    return;
+                };
+                let subscription = Subscription {
+                    table: &manager.config.tables.events,
+                    manager: "events",
+                    center: &center,
+                    project: &project,
+                };
+
+                connection::supervise(
+                    &tenant,
+                    &manager.config,
+                    subscription,
+                    &health,
+                    &stop,
+                    |notification| manager.handle_actions(&center, &project, notification),
+                    || manager.reconcile(&center, &project),
+                )
+                .await;
 
-            // WARNING: not sure if it's ok
-            manager.db.use_ns(&center).use_db(&project).await.unwrap();
+                health.retire();
+            }
+            .instrument(span),
+        );
+    }
 
-            let mut events_stream = manager.db.select("events").live().await.unwrap();
-            while let Some(result) = events_stream.next().await {
-                match result {
-                    Ok(notification) => {
-                        manager
-                            .handle_actions(center.as_str(), project.as_str(), notification)
-                            .await;
-                    }
-                    Err(error) => eprintln!("{error}"),
-                }
+    /// Schedules the events created or activated while the live query was
+    /// down.
+    async fn reconcile(&self, center: &str, project: &str) -> Result<()> {
+        if self.is_suspended(center, project) {
+            return Ok(());
+        }
+
+        let jobs: HashSet<uuid::Uuid> = self.jobs.lock().await.keys().copied().collect();
+
+        for mut event in self.select_events(center, project).await? {
+            // a job id left behind by a removed job does not count
+            if event.job_id.is_some_and(|uuid| jobs.contains(&uuid.0)) {
+                continue;
             }
-        });
+
+            match event.status.as_deref() {
+                Some("done") | Some("failed") => continue,
+                _ if !event.active => continue,
+                _ => {}
+            }
+
+            self.create_job(&mut event, center, project).await?;
+            self.update_event(center, project, event).await?;
+        }
+
+        Ok(())
     }
 
-    async fn select_events(&self, center: &str, project: &str) -> Vec<Event> {
-        let sql = format!("USE NS {} DB {}; SELECT * FROM events;", center, project);
-        let mut res = self.db.query(sql).await.unwrap();
+    fn is_suspended(&self, center: &str, project: &str) -> bool {
+        self.suspended
+            .lock()
+            .unwrap()
+            .contains(&format!("{center}/{project}"))
+    }
 
-        res.take(res.num_statements() - 1).unwrap()
+    fn runs(&self, center: &str, project: &str) -> Arc<RwLock<()>> {
+        self.runs
+            .lock()
+            .unwrap()
+            .entry(format!("{center}/{project}"))
+            .or_default()
+            .clone()
     }
 
-    async fn select_event(&self, center: &str, project: &str, id: &Thing) -> Option<Event> {
-        let sql = format!("USE NS {} DB {}; SELECT * FROM $b_id;", center, project);
-        let mut res = self.db.query(sql).bind(("b_id", id)).await.unwrap();
+    /// Stops scheduling the events of a project, removes its cron jobs and
+    /// waits for the executions still running. Those write their event back
+    /// when they end, so the caller keeps the guard while it updates them.
+    async fn suspend(&self, center: &str, project: &str) -> Result<OwnedRwLockWriteGuard<()>> {
+        self.suspended
+            .lock()
+            .unwrap()
+            .insert(format!("{center}/{project}"));
 
-        res.take(res.num_statements() - 1).unwrap()
+        self.remove_jobs(center, project).await?;
+
+        Ok(self.runs(center, project).write_owned().await)
     }
 
-    async fn update_event(&self, center: &str, project: &str, event: Event) {
-        let id = event.id.as_ref().unwrap().clone();
+    async fn remove_jobs(&self, center: &str, project: &str) -> Result<()> {
+        let jobs: Vec<uuid::Uuid> = self
+            .jobs
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, job)| job.center == center && job.project == project)
+            .map(|(uuid, _)| *uuid)
+            .collect();
+
+        for uuid in jobs {
+            self.remove_job(uuid).await.tenant(center, project)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unschedules the events of a paused project, they keep their `active`
+    /// flag so resuming schedules them again.
+    async fn pause(&self, center: &str, project: &str) -> Result<()> {
+        let _runs = self.suspend(center, project).await?;
 
         let sql = format!(
-            "USE NS {} DB {}; UPDATE $b_id CONTENT $b_content;",
-            center, project
+            "UPDATE {} SET job_id = NONE, status = 'paused' WHERE status IN ['scheduled', 'running'];",
+            self.config.tables.events
         );
 
-        self.db
-            .query(sql)
+        self.session(center, project)
+            .await?
+            .execute(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, project)
+    }
+
+    async fn resume(&self, center: &str, project: &str) -> Result<()> {
+        self.suspended
+            .lock()
+            .unwrap()
+            .remove(&format!("{center}/{project}"));
+
+        self.reconcile(center, project).await
+    }
+
+    /// Deactivates every event of a finished project for good.
+    async fn finish(&self, center: &str, project: &str) -> Result<()> {
+        let _runs = self.suspend(center, project).await?;
+
+        let sql = format!(
+            "UPDATE {} SET job_id = NONE, active = false;",
+            self.config.tables.events
+        );
+
+        self.session(center, project)
+            .await?
+            .execute(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, project)
+    }
+
+    async fn session(&self, center: &str, project: &str) -> Result<Session> {
+        connection::tenant_session(&self.db, &self.config, center, project).await
+    }
+
+    async fn select_events(&self, center: &str, project: &str) -> Result<Vec<Event>> {
+        let session = self.session(center, project).await?;
+        let sql = format!("SELECT * FROM {};", self.config.tables.events);
+        let mut res = session
+            .query(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, project)?;
+
+        res.take(res.num_statements() - 1)
+            .query(&sql)
+            .tenant(center, project)
+    }
+
+    async fn select_event(&self, center: &str, project: &str, id: &Thing) -> Result<Option<Event>> {
+        let session = self.session(center, project).await?;
+        let mut res = session
+            .query("SELECT * FROM $b_id;")
             .bind(("b_id", id))
+            .await
+            .record(id)
+            .tenant(center, project)?;
+
+        res.take(res.num_statements() - 1)
+            .record(id)
+            .tenant(center, project)
+    }
+
+    async fn update_event(&self, center: &str, project: &str, event: Event) -> Result<()> {
+        let id = event_id(&event).tenant(center, project)?.clone();
+
+        self.session(center, project)
+            .await?
+            .query("UPDATE $b_id CONTENT $b_content;")
+            .bind(("b_id", &id))
             .bind(("b_content", event))
             .await
-            .unwrap();
+            .record(&id)
+            .tenant(center, project)?
+            .check()
+            .record(&id)
+            .tenant(center, project)?;
+
+        Ok(())
     }
 
-    async fn handle_actions(&self, center: &str, project: &str, notification: Notification<Event>) {
+    /// Detaches an event from a job that is going away.
+    async fn release(&self, job: &ScheduledJob) -> Result<()> {
+        let Some(mut event) = self
+            .select_event(&job.center, &job.project, &job.event)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        event.job_id = None;
+        if event.status.as_deref() == Some("running") {
+            event.status = Some("scheduled".to_string());
+        }
+
+        self.update_event(&job.center, &job.project, event).await
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        action = ?notification.action,
+        record = notification.data.id.as_ref().map(tracing::field::display),
+    ))]
+    async fn handle_actions(
+        &self,
+        center: &str,
+        project: &str,
+        notification: Notification<Event>,
+    ) -> Result<()> {
         let mut event = notification.data;
 
+        if self.is_suspended(center, project) {
+            return Ok(());
+        }
+
         match notification.action {
             surrealdb::Action::Create => {
-                self.create_job(&mut event, center, project).await.unwrap();
-                self.update_event(center, project, event).await;
+                self.create_job(&mut event, center, project).await?;
+                self.update_event(center, project, event).await?;
             }
             surrealdb::Action::Update => {
                 if event.job_id.is_none() && event.active {
-                    self.create_job(&mut event, center, project).await.unwrap();
-                    self.update_event(center, project, event).await;
+                    self.create_job(&mut event, center, project).await?;
+                    self.update_event(center, project, event).await?;
                 } else if event.job_id.is_some() && !event.active && event.status.is_none() {
                     event.status = Some("scheduled".to_string());
-                    self.update_event(center, project, event).await;
+                    self.update_event(center, project, event).await?;
                 }
             }
             surrealdb::Action::Delete => {
-                // println!("In center: {} and {}", center, project);
-                // println!("Event deleted: {:?}", event);
+                // a deleted event must not fire again
+                let id = event_id(&event).tenant(center, project)?;
+                let jobs: Vec<uuid::Uuid> = self
+                    .jobs
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(_, job)| {
+                        job.center == center && job.project == project && &job.event == id
+                    })
+                    .map(|(uuid, _)| *uuid)
+                    .collect();
+
+                for uuid in jobs {
+                    self.remove_job(uuid)
+                        .await
+                        .record(id)
+                        .tenant(center, project)?;
+                }
             }
             _ => {}
         }
+
+        Ok(())
     }
 
-    pub async fn create_job(
-        &self,
-        event: &mut Event,
-        center: &str,
-        project: &str,
-    ) -> Result<(), JobSchedulerError> {
+    pub async fn create_job(&self, event: &mut Event, center: &str, project: &str) -> Result<()> {
         let event_shedule = event.schedule.clone();
+        let scheduled = ScheduledJob {
+            center: center.to_string(),
+            project: project.to_string(),
+            event: event_id(event).tenant(center, project)?.clone(),
+        };
+        let id = scheduled.event.clone();
         let center = center.to_string();
         let manager = self.clone();
         let project = project.to_string();
-        let id = event.id.as_ref().unwrap().clone();
 
         let job = Job::new_async(event_shedule.as_str(), move |uuid, lock| {
             let id = id.clone();
@@ -143,48 +383,68 @@ impl EventsManager {
             let project = project.to_string();
 
             Box::pin(async move {
-                manager
-                    .handle_status(id, uuid, lock, center.as_str(), project.as_str())
-                    .await
+                // no new executions once the process is going down
+                if manager.shutdown.is_triggered() {
+                    return;
+                }
+
+                let status = manager.handle_status(id, uuid, lock, &center, &project);
+                manager.shutdown.track(status).await
             })
-        })?;
+        })
+        .record(&scheduled.event)
+        .tenant(&scheduled.center, &scheduled.project)?;
 
         let scheduler = self.sched.lock().await;
-        let res = scheduler.add(job).await?;
+        let res = scheduler
+            .add(job)
+            .await
+            .record(&scheduled.event)
+            .tenant(&scheduled.center, &scheduled.project)?;
         drop(scheduler); // alternative to scope
 
+        metrics::SCHEDULED_JOBS
+            .with_label_values(&[&scheduled.center, &scheduled.project])
+            .inc();
+        self.jobs.lock().await.insert(res, scheduled);
+
         event.job_id = Some(res.into());
         event.status = Some("scheduled".to_string());
 
         Ok(())
     }
 
-    pub async fn event_execute(&self, center: &str, project: &str, script: &str) -> Result<(), ()> {
-        let sql = format!(
-            "USE NS {} DB {}; fn::on_cron('{}');",
-            center, project, script
-        );
-        let res = self.db.query(sql).await;
-
-        match res {
-            Ok(mut r) => {
-                let _: Option<String> = r.take(r.num_statements() - 1).map_err(|e| {
-                    eprintln!(
-                        "Error: {:?};\ncenter = {center} -> project = {project}: script = {script}",
-                        e
-                    )
-                })?;
-
-                Ok(())
-            }
-            Err(e) => {
-                eprintln!("Error: {:?}", e);
+    async fn remove_job(&self, uuid: uuid::Uuid) -> Result<()> {
+        self.sched.lock().await.remove(&uuid).await?;
 
-                Err(())
-            }
+        if let Some(job) = self.jobs.lock().await.remove(&uuid) {
+            metrics::SCHEDULED_JOBS
+                .with_label_values(&[&job.center, &job.project])
+                .dec();
         }
+
+        Ok(())
     }
 
+    pub async fn event_execute(&self, center: &str, project: &str, script: &str) -> Result<()> {
+        let sql = "fn::on_cron($b_script);";
+        let session = self.session(center, project).await?;
+        let mut res = session
+            .query(sql)
+            .bind(("b_script", script))
+            .await
+            .query(sql)
+            .tenant(center, project)?;
+
+        let _: Option<String> = res
+            .take(res.num_statements() - 1)
+            .query(sql)
+            .tenant(center, project)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "job", skip(self, lock), fields(record = %id, job = %uuid))]
     pub async fn handle_status(
         &self,
         id: Thing,
@@ -193,34 +453,63 @@ impl EventsManager {
         center: &str,
         project: &str,
     ) {
-        if let Some(mut event) = self.select_event(center, project, &id).await {
+        let _run = self.runs(center, project).read_owned().await;
+        // paused or finished while this execution was waiting
+        if self.is_suspended(center, project) {
+            return;
+        }
+
+        let event = match self.select_event(center, project, &id).await {
+            Ok(event) => event,
+            Err(error) => {
+                error!(%error);
+                return;
+            }
+        };
+
+        if let Some(mut event) = event {
             if !event.active {
                 event.status = Some("scheduled".to_string());
-                self.update_event(center, project, event).await;
+                if let Err(error) = self.update_event(center, project, event).await {
+                    error!(%error);
+                }
 
                 return;
             }
 
             self.event_check(&mut event, &mut lock, uuid).await;
 
+            let timer = metrics::CRON_DURATION
+                .with_label_values(&[center, project])
+                .start_timer();
             let pre = self
                 .event_execute(center, project, event.script.as_str())
                 .await;
+            timer.observe_duration();
 
-            match pre {
-                Ok(_) => {}
-                Err(_) => {
-                    event.status = Some("failed".to_string());
-                    event.active = false;
+            let outcome = if pre.is_ok() { "ok" } else { "failed" };
+            metrics::CRON_EXECUTIONS
+                .with_label_values(&[center, project, outcome])
+                .inc();
 
-                    match self.sched.lock().await.remove(&uuid).await {
-                        Ok(_) => event.job_id = None,
-                        Err(e) => eprintln!("Error: {e:?}\n;"),
-                    }
+            if let Err(error) = pre {
+                error!(%error);
+
+                event.status = Some("failed".to_string());
+                event.active = false;
+            }
+
+            // done or failed, the job must not fire again
+            if !event.active {
+                match self.remove_job(uuid).await {
+                    Ok(()) => event.job_id = None,
+                    Err(error) => error!(%error, "Failed to remove job"),
                 }
             }
 
-            self.update_event(center, project, event).await;
+            if let Err(error) = self.update_event(center, project, event).await {
+                error!(%error);
+            }
         }
     }
 
@@ -228,7 +517,7 @@ impl EventsManager {
         let next_tick = match lock.next_tick_for_job(uuid).await {
             Ok(Some(ts)) => Some(ts),
             Ok(None) => {
-                println!("Job Done!");
+                info!("Job done");
 
                 event.status = Some("done".to_string());
                 event.active = false;
@@ -237,7 +526,7 @@ impl EventsManager {
             }
             _ => {
                 // unreachable
-                eprintln!("Could not get next tick from job");
+                error!("Could not get next tick from job");
 
                 return;
             }
@@ -252,7 +541,7 @@ impl EventsManager {
 
             if let Some(until) = event.until.clone() {
                 if *until < ts {
-                    println!("Job Done!");
+                    info!("Job done");
 
                     event.status = Some("done".to_string());
                     event.active = false;
@@ -273,27 +562,105 @@ impl EventsManager {
 
 #[async_trait::async_trait]
 impl ProjectsManagerTrait for EventsManager {
-    async fn on_init(&self, project: &str, center: &str) {
-        let events = self.select_events(center, project).await;
+    async fn on_init(&self, project: &Project, center: &str) -> Result<()> {
+        let suspended = project.state != ProjectState::Active;
+        let project = project.name.as_str();
+
+        if suspended {
+            self.suspended
+                .lock()
+                .unwrap()
+                .insert(format!("{center}/{project}"));
+        }
+
+        let events = self.select_events(center, project).await?;
         for mut event in events {
             event.job_id = None;
 
-            if let Some(ref status) = event.status {
+            if suspended {
+                // scheduled once the project is resumed
+            } else if let Some(ref status) = event.status {
                 if status != "done" && status != "failed" {
-                    self.create_job(&mut event, center, project).await.unwrap();
+                    // one broken schedule must not keep the others from running
+                    if let Err(error) = self.create_job(&mut event, center, project).await {
+                        error!(%error);
+                        continue;
+                    }
                 }
             }
 
-            self.update_event(center, project, event).await;
+            self.update_event(center, project, event).await?;
         }
 
         self.spawn_stream(center, project);
+
+        Ok(())
     }
 
-    async fn on_project_create(&self, project: &str, center: &str) {
+    async fn on_project_create(&self, project: &str, center: &str) -> Result<()> {
         self.spawn_stream(center, project);
+
+        Ok(())
+    }
+
+    async fn on_project_update(&self, old: &Project, new: &Project, center: &str) -> Result<()> {
+        match (&old.state, &new.state) {
+            (ProjectState::Active, ProjectState::Paused) => self.pause(center, &new.name).await,
+            (ProjectState::Paused, ProjectState::Active) => self.resume(center, &new.name).await,
+            (ProjectState::Active | ProjectState::Paused, ProjectState::Finished) => {
+                self.finish(center, &new.name).await
+            }
+            _ => Ok(()),
+        }
     }
 
-    async fn on_project_update(&self, _project: &str) {}
-    async fn on_project_delete(&self, _project: &str) {}
+    /// Stops the project's live stream and removes its cron jobs, the events
+    /// stay in the project database, which is archived rather than removed.
+    async fn on_project_delete(&self, project: &str, center: &str) -> Result<()> {
+        if let Some(stream) = self
+            .streams
+            .lock()
+            .unwrap()
+            .remove(&format!("{center}/{project}"))
+        {
+            stream.trigger();
+        }
+        self.suspended
+            .lock()
+            .unwrap()
+            .remove(&format!("{center}/{project}"));
+        self.runs
+            .lock()
+            .unwrap()
+            .remove(&format!("{center}/{project}"));
+
+        self.remove_jobs(center, project).await
+    }
+
+    /// Stops the scheduler and detaches every event from its job, a
+    /// `running` event goes back to `scheduled` so `on_init` picks it up on
+    /// the next start.
+    async fn on_shutdown(&self) -> Result<()> {
+        self.sched.lock().await.shutdown().await?;
+
+        let jobs = std::mem::take(&mut *self.jobs.lock().await);
+        for job in jobs.into_values() {
+            metrics::SCHEDULED_JOBS
+                .with_label_values(&[&job.center, &job.project])
+                .dec();
+
+            if let Err(error) = self.release(&job).await {
+                error!(%error, "Failed to release event");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn event_id(event: &Event) -> Result<&Thing> {
+    event
+        .id
+        .as_ref()
+        .ok_or_else(|| SupervisorError::invalid("event without id"))
 }
@@ -1,126 +1,218 @@
-use futures::stream::StreamExt;
-use surrealdb::engine::any::{self, Any};
-use surrealdb::opt::auth::Root;
-use surrealdb::{Notification, Surreal};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use crate::models::user::{IntervUser, IntervUserPrev, UserState};
+use surrealdb::engine::any::Any;
+use surrealdb::{Notification, Surreal};
+use tracing::{error, info_span, Instrument};
+
+use crate::config::Config;
+use crate::connection::{self, Session, Subscription};
+use crate::error::{Result, ResultExt};
+use crate::health;
+use crate::models::project::{Project, ProjectState};
+use crate::models::user::{IntervUser, IntervUserPrev};
+use crate::modules::join::sync::StateSync;
 use crate::modules::projects::manager::ProjectsManagerTrait;
-
-use super::manager::Credentials;
+use crate::shutdown::Shutdown;
 
 #[derive(Clone)]
 pub struct IntervUsersManager {
     db: Surreal<Any>,
-    cred: Credentials,
+    config: Arc<Config>,
+    sync: StateSync,
+    /// Stops the live stream of each `center/project`.
+    streams: Arc<Mutex<HashMap<String, Shutdown>>>,
+    shutdown: Shutdown,
 }
 
 impl IntervUsersManager {
-    pub async fn new(url: impl Into<String>, cred: Credentials) -> Self {
-        let db = any::connect(format!("ws://{}", url.into()))
-            .await
-            .expect("Failed to connect to database");
-
-        let foo = Root {
-            username: cred.user.as_str(),
-            password: cred.pass.as_str(),
-        };
-
-        db.signin(foo).await.expect("Failed to signin");
-
-        Self { db, cred }
+    pub async fn new(config: Arc<Config>, shutdown: Shutdown) -> Self {
+        let db = connection::connect(&config).await;
+
+        Self {
+            sync: StateSync::new(&db, &config),
+            db,
+            config,
+            streams: Arc::default(),
+            shutdown,
+        }
     }
 
     fn spawn_stream(&self, center: impl Into<String>, project: impl Into<String>) {
         let manager = self.clone();
         let project = project.into();
         let center = center.into();
+        let span = info_span!(
+            "users",
+            center = %center,
+            project = %project,
+            table = %self.config.tables.users,
+        );
+
+        let stop = self.shutdown.child();
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(format!("{center}/{project}"), stop.clone());
+
+        let health = health::register(format!("interv_users:{center}/{project}"));
+
+        self.shutdown.spawn(
+            async move {
+                let connected = connection::tenant_until(
+                    &manager.db,
+                    &manager.config,
+                    &center,
+                    &project,
+                    &stop,
+                )
+                .await;
+                let Some(tenant) = connected else {
+                    health.retire();
+                    return;
+                };
+                let subscription = Subscription {
+                    table: &manager.config.tables.users,
+                    manager: "interv_users",
+                    center: &center,
+                    project: &project,
+                };
+
+                connection::supervise(
+                    &tenant,
+                    &manager.config,
+                    subscription,
+                    &health,
+                    &stop,
+                    |notification| manager.handle_actions(&center, &project, notification),
+                    || manager.reconcile(&center, &project),
+                )
+                .await;
+
+                health.retire();
+            }
+            .instrument(span),
+        );
+    }
 
-        tokio::spawn(async move {
-            let manager = manager.clone();
-
-            // WARNING: not sure if it's ok
-            manager.db.use_ns(&center).use_db(&project).await.unwrap();
-
-            let mut users_stream = manager.db.select("users").live().await.unwrap();
-            while let Some(result) = users_stream.next().await {
-                match result {
-                    Ok(notification) => {
-                        manager
-                            .handle_actions(center.as_str(), project.as_str(), notification)
-                            .await;
-                    }
-                    Err(error) => eprintln!("{error}"),
-                }
+    /// Pushes the state of every project user to its join, covering the
+    /// updates missed while the live query was down.
+    async fn reconcile(&self, center: &str, project: &str) -> Result<()> {
+        let session = self.session(center, project).await?;
+        let sql = format!("SELECT * FROM {};", self.config.tables.users);
+        let mut res = session
+            .query(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, project)?;
+        let users: Vec<IntervUserPrev> = res
+            .take(res.num_statements() - 1)
+            .query(&sql)
+            .tenant(center, project)?;
+
+        for user in users {
+            // keep going, the next pass retries the ones that failed
+            if let Err(error) = self.sync_state(center, project, user.into()).await {
+                error!(%error);
             }
-        });
+        }
+
+        Ok(())
     }
 
+    #[tracing::instrument(skip_all, fields(
+        action = ?notification.action,
+        record = %notification.data.id,
+    ))]
     async fn handle_actions(
         &self,
         center: &str,
         project: &str,
         notification: Notification<IntervUserPrev>,
-    ) {
+    ) -> Result<()> {
         let user: IntervUser = notification.data.into();
 
         match notification.action {
             surrealdb::Action::Update => {
                 // println!("User updated: {}", user.id);
 
-                match user.state {
-                    UserState::Completed | UserState::Exited => {
-                        let state: String = user.state.into();
-
-                        self.db.use_ns(center).use_db(project).await.unwrap();
-                        self.db
-                            .query(r#"
-                                LET $q_score = SELECT VALUE score FROM ONLY (
-                                    SELECT created, score FROM ONLY scores WHERE user IS $b_id ORDER BY created DESC LIMIT 1
-                                ) LIMIT 1;
-
-                                USE NS global DB main;
-
-                                BEGIN TRANSACTION;
-                                    UPDATE join SET state = $b_state, score = $q_score, updated = time::now() WHERE in IS $b_id;
-                                    UPDATE $b_id SET project = NONE; -- should be done by join events
-                                COMMIT TRANSACTION;
-                            "#)
-                            .bind(("b_id", user.id))
-                            .bind(("b_state", state))
-                            .await
-                            .unwrap();
-                    }
-                    UserState::Active | UserState::Standby => {
-                        let state: String = user.state.into();
-
-                        self.db.use_ns("global").use_db("main").await.unwrap();
-                        self.db
-                            .query("UPDATE join SET state = $b_state, updated = time::now() WHERE in IS $b_id;")
-                            .bind(("b_id", user.id))
-                            .bind(("b_state", state))
-                            .await
-                            .unwrap();
-                    }
-                    _ => {}
-                }
+                self.sync_state(center, project, user).await?;
             }
             surrealdb::Action::Create => { /* println!("User created: {}", user.id) */ }
             surrealdb::Action::Delete => { /* println!("User deleted: {}", user.id) */ }
             _ => {}
         }
+
+        Ok(())
+    }
+
+    /// Moves the users of a project in bulk, the live stream then syncs each
+    /// of them with its join.
+    async fn set_states(&self, center: &str, project: &str, set: &str) -> Result<()> {
+        let sql = format!("UPDATE {} {set};", self.config.tables.users);
+
+        self.session(center, project)
+            .await?
+            .execute(&sql)
+            .await
+            .query(&sql)
+            .tenant(center, project)
+    }
+
+    async fn session(&self, center: &str, project: &str) -> Result<Session> {
+        connection::tenant_session(&self.db, &self.config, center, project).await
+    }
+
+    async fn sync_state(&self, center: &str, project: &str, user: IntervUser) -> Result<()> {
+        self.sync.settle(center, project, &user.id).await?;
+
+        Ok(())
     }
 }
 
 #[async_trait::async_trait]
 impl ProjectsManagerTrait for IntervUsersManager {
-    async fn on_init(&self, project: &str, center: &str) {
-        self.spawn_stream(center, project);
+    async fn on_init(&self, project: &Project, center: &str) -> Result<()> {
+        self.spawn_stream(center, &project.name);
+
+        Ok(())
     }
 
-    async fn on_project_create(&self, project: &str, center: &str) {
+    async fn on_project_create(&self, project: &str, center: &str) -> Result<()> {
         self.spawn_stream(center, project);
+
+        Ok(())
+    }
+
+    /// Pausing puts the active users on standby and resuming brings back
+    /// only those, finishing completes everyone still taking part.
+    async fn on_project_update(&self, old: &Project, new: &Project, center: &str) -> Result<()> {
+        let set = match (&old.state, &new.state) {
+            (ProjectState::Active, ProjectState::Paused) => {
+                "SET state = 'standby', paused = true WHERE state = 'active'"
+            }
+            (ProjectState::Paused, ProjectState::Active) => {
+                "SET state = 'active', paused = NONE WHERE paused = true"
+            }
+            (ProjectState::Active | ProjectState::Paused, ProjectState::Finished) => {
+                "SET state = 'completed', paused = NONE WHERE state IN ['active', 'standby']"
+            }
+            _ => return Ok(()),
+        };
+
+        self.set_states(center, &new.name, set).await
     }
 
-    async fn on_project_update(&self, _project: &str) {}
-    async fn on_project_delete(&self, _project: &str) {}
+    async fn on_project_delete(&self, project: &str, center: &str) -> Result<()> {
+        if let Some(stream) = self
+            .streams
+            .lock()
+            .unwrap()
+            .remove(&format!("{center}/{project}"))
+        {
+            stream.trigger();
+        }
+
+        Ok(())
+    }
 }
@@ -1,224 +1,810 @@
-use std::sync::Arc;
-
-use futures::stream::StreamExt;
-use surrealdb::engine::any::{self, Any};
-use surrealdb::engine::remote::http::Http;
-use surrealdb::opt::auth::Root;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::sql::statements::DefineStatement;
+use surrealdb::sql::{Permission, Statement, Strand, Thing};
 use surrealdb::{Notification, Surreal};
-use tempdir::TempDir;
-use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, info, warn, Instrument};
 
+use crate::config::Config;
+use crate::connection::{self, Session, Subscription};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::health;
+use crate::ident::Ident;
 use crate::models::center::Center;
-use crate::models::project::Project;
+use crate::models::project::{Project, ProjectState};
+use crate::modules::centers::manager::CentersManagerTrait;
+use crate::shutdown::{Shutdown, DRAIN_TIMEOUT};
 
 use super::events::EventsManager;
 use super::interv_users::IntervUsersManager;
+use super::migrations::{self, Migrations};
+use super::rollout::Rollout;
+
+struct Listener(pub Arc<dyn ProjectsManagerTrait>);
+
+const SCOPE_TOKEN: &str = "user_scope";
+/// Keeps the secret replaced by a rotation valid for `scope.token_grace`.
+const PREVIOUS_SCOPE_TOKEN: &str = "user_scope_previous";
 
+/// Center and last known version of a project, enough to reach its database
+/// once the record is gone and to tell what an update changed.
 #[derive(Clone)]
-pub struct Credentials {
-    pub user: String,
-    pub pass: String,
+struct Tenant {
+    center: String,
+    project: Project,
 }
 
-struct Listener(pub Arc<dyn ProjectsManagerTrait>);
+/// The tokens of `INFO FOR SCOPE user`.
+#[derive(Deserialize)]
+struct ScopeInfo {
+    tokens: BTreeMap<String, String>,
+}
+
+/// The part of `INFO FOR DB` needed to redefine the tables.
+#[derive(Deserialize)]
+struct DbInfo {
+    tables: BTreeMap<String, String>,
+}
 
 pub struct ProjectsManager {
     db: Surreal<Any>,
-    db_url: String,
-    cred: Credentials,
+    main: Session,
+    config: Arc<Config>,
     listeners: Vec<Listener>,
+    migrations: Migrations,
+    known: Mutex<HashMap<String, Tenant>>,
+    /// Ends the grace period of the previous token of each `center/project`.
+    token_grace: Mutex<HashMap<String, Shutdown>>,
+    shutdown: Shutdown,
 }
 
 impl ProjectsManager {
-    pub async fn new(url: impl Into<String>, cred: Credentials) -> Self {
-        let url: String = url.into();
-
-        let db = any::connect(format!("ws://{}", url))
-            .await
-            .expect("Failed to connect to database");
-
-        let foo = Root {
-            username: cred.user.as_str(),
-            password: cred.pass.as_str(),
-        };
-
-        db.signin(foo).await.expect("Failed to signin");
-
-        db.use_ns("global")
-            .use_db("main")
-            .await
-            .expect("Failed to use ns and db");
+    pub async fn new(config: Arc<Config>, shutdown: Shutdown) -> Self {
+        let db = connection::connect(&config).await;
+        let main = Session::new(&db, &config.namespaces.global, &config.namespaces.main);
+        let migrations = Migrations::new(db.clone(), config.clone());
 
         let listeners = vec![
-            Listener(Arc::new(EventsManager::new(&url, cred.clone()).await)),
-            Listener(Arc::new(IntervUsersManager::new(&url, cred.clone()).await)),
+            Listener(Arc::new(
+                EventsManager::new(config.clone(), shutdown.clone()).await,
+            )),
+            Listener(Arc::new(
+                IntervUsersManager::new(config.clone(), shutdown.clone()).await,
+            )),
         ];
 
         Self {
             db,
-            cred,
-            db_url: url.to_string(),
+            main,
+            config,
             listeners,
+            migrations,
+            known: Mutex::new(HashMap::new()),
+            token_grace: Mutex::new(HashMap::new()),
+            shutdown,
         }
     }
 
-    pub async fn start(&mut self) -> Result<(), &str> {
-        self.init_existing()
-            .await
-            .map_err(|_| "Failed to init existing projects")?;
+    #[tracing::instrument(name = "projects", skip_all, fields(table = %self.config.tables.projects))]
+    pub async fn start(&self) -> Result<()> {
+        let health = health::register("projects");
 
-        let mut stream = self
-            .db
-            .select("projects")
-            .live()
+        match Rollout::new(self.db.clone(), self.config.clone())
+            .on_start()
             .await
-            .map_err(|_| "Failed to get projects")?;
-
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(notification) => {
-                    if let Err(error) = self.handle_actions(notification).await {
-                        eprintln!("{error}");
-                    }
-                }
-
-                Err(error) => eprintln!("{error}"),
+        {
+            Ok(report) if report.halted.is_empty() => {
+                info!(migrated = report.migrated.len(), "Projects migrated");
             }
+            Ok(_) => {}
+            Err(error) => error!(%error, "Failed to roll out migrations"),
         }
 
+        self.init_existing().await?;
+        health::mark_initialized();
+
+        let main = connection::Tenant::root(
+            &self.db,
+            &self.config.namespaces.global,
+            &self.config.namespaces.main,
+        );
+        let subscription = Subscription {
+            table: &self.config.tables.projects,
+            manager: "projects",
+            center: "",
+            project: "",
+        };
+
+        connection::supervise(
+            &main,
+            &self.config,
+            subscription,
+            &health,
+            &self.shutdown,
+            |notification| self.handle_actions(notification),
+            || self.reconcile(),
+        )
+        .await;
+
+        health.retire();
+        self.stop().await;
+
         Ok(())
     }
 
-    async fn init_existing(&self) -> Result<(), &str> {
-        let projects: Vec<Project> = self
-            .db
-            .select("projects")
-            .await
-            .map_err(|_| "Failed to get projects")?;
+    /// Lets the tenant streams and running cron executions finish, then has
+    /// every listener persist its state.
+    async fn stop(&self) {
+        info!("Shutting down");
+
+        if !self.shutdown.drain(DRAIN_TIMEOUT).await {
+            warn!("Timed out waiting for in-flight work");
+        }
+
+        for handler in &self.listeners {
+            if let Err(error) = handler.0.on_shutdown().await {
+                error!(%error);
+            }
+        }
+    }
+
+    async fn init_existing(&self) -> Result<()> {
+        let projects = self.select_projects().await?;
 
         for project in projects {
-            let center: Option<Center> = self
-                .db
-                .select(&project.center)
+            let checked = self
+                .select_center(&project)
                 .await
-                .map_err(|_| "Failed to get center")?;
+                .and_then(|center| check_names(&project, &center).map(|_| center))
+                .and_then(|center| check_state(&project).map(|_| center));
+
+            let center = match checked {
+                Ok(center) => center,
+                Err(error) => {
+                    error!(%error, project = %project.name, "Skipping project");
+                    continue;
+                }
+            };
+
+            self.remember(&project, &center);
 
-            let center = center.unwrap();
+            if let Err(error) = self
+                .restore_token(&center.name, &project.name, &project.token)
+                .await
+            {
+                error!(%error, project = %project.name, "Failed to restore the scope token");
+            }
 
             for handler in &self.listeners {
-                handler.0.on_init(&project.name, &center.name).await;
+                if let Err(error) = handler.0.on_init(&project, &center.name).await {
+                    error!(%error);
+                }
             }
         }
 
         Ok(())
     }
 
-    async fn handle_actions(&self, notification: Notification<Project>) -> Result<(), &str> {
+    /// Catches up with projects created, updated or deleted while the live
+    /// query was down.
+    async fn reconcile(&self) -> Result<()> {
+        let projects = self.select_projects().await?;
+
+        let current: HashSet<String> = projects
+            .iter()
+            .filter_map(|p| p.id.as_ref().map(Thing::to_string))
+            .collect();
+        let known = self.known.lock().unwrap().clone();
+
+        let created = projects.iter().filter(|p| {
+            p.id.as_ref()
+                .is_some_and(|id| !known.contains_key(&id.to_string()))
+        });
+
+        for project in created {
+            if let Err(error) = self.on_create(project).await {
+                error!(%error);
+            }
+        }
+
+        let updated = projects.iter().filter(|p| {
+            p.id.as_ref()
+                .and_then(|id| known.get(&id.to_string()))
+                .is_some_and(|tenant| {
+                    tenant.project.state != p.state || tenant.project.token != p.token
+                })
+        });
+
+        for project in updated {
+            if let Err(error) = self.on_update(project.clone()).await {
+                error!(%error);
+            }
+        }
+
+        for (id, tenant) in known.into_iter().filter(|(id, _)| !current.contains(id)) {
+            self.known.lock().unwrap().remove(&id);
+
+            if let Err(error) = self.on_delete(tenant).await {
+                error!(%error);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remember(&self, project: &Project, center: &Center) {
+        if let Some(id) = &project.id {
+            let tenant = Tenant {
+                center: center.name.clone(),
+                project: project.clone(),
+            };
+
+            self.known.lock().unwrap().insert(id.to_string(), tenant);
+        }
+    }
+
+    async fn select_projects(&self) -> Result<Vec<Project>> {
+        let sql = format!("SELECT * FROM {};", self.config.tables.projects);
+        let mut res = self.main.query(&sql).await.query(&sql)?;
+
+        res.take(res.num_statements() - 1).query(&sql)
+    }
+
+    async fn select_center(&self, project: &Project) -> Result<Center> {
+        let mut res = self
+            .main
+            .query("SELECT * FROM ONLY $b_id;")
+            .bind(("b_id", &project.center))
+            .await
+            .record(&project.center)?;
+
+        let center: Option<Center> = res.take(res.num_statements() - 1).record(&project.center)?;
+
+        center.ok_or_else(|| SupervisorError::not_found("center").record(&project.center))
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        action = ?notification.action,
+        record = notification.data.id.as_ref().map(tracing::field::display),
+    ))]
+    async fn handle_actions(&self, notification: Notification<Project>) -> Result<()> {
         let project = notification.data;
 
         match notification.action {
-            surrealdb::Action::Create => {
-                let center: Option<Center> = self
-                    .db
-                    .select(&project.center)
+            surrealdb::Action::Create => self.on_create(&project).await?,
+            surrealdb::Action::Update => self.on_update(project).await?,
+            surrealdb::Action::Delete => {
+                let known = project
+                    .id
+                    .as_ref()
+                    .and_then(|id| self.known.lock().unwrap().remove(&id.to_string()));
+
+                let tenant = match known {
+                    Some(tenant) => tenant,
+                    None => {
+                        // never picked up, its names were not checked yet and
+                        // would make the backup path
+                        let center = self.select_center(&project).await?;
+                        check_names(&project, &center)?;
+
+                        Tenant {
+                            center: center.name,
+                            project,
+                        }
+                    }
+                };
+
+                self.on_delete(tenant).await?;
+            }
+            action => debug!(?action, "Action not supported"),
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(project = %project.name, center))]
+    async fn on_create(&self, project: &Project) -> Result<()> {
+        let center = self.select_center(project).await?;
+        tracing::Span::current().record("center", center.name.as_str());
+        check_names(project, &center)?;
+        check_state(project)?;
+
+        // nothing may reach the database before it was imported, a failed
+        // import removes it again
+        self.execute_migrations(&center.name, &project.name).await?;
+
+        self.remember(project, &center);
+
+        self.define_token(&center.name, &project.name, SCOPE_TOKEN, &project.token)
+            .await?;
+
+        for handler in &self.listeners {
+            let created = handler
+                .0
+                .on_project_create(&project.name, &center.name)
+                .await;
+
+            if let Err(error) = created {
+                error!(%error);
+            }
+        }
+
+        info!("Project created");
+
+        Ok(())
+    }
+
+    /// Hands the previous and the new version of a project to the listeners,
+    /// then makes the database read-only once the project is finished. The
+    /// new version is only remembered once all of that went through, so that
+    /// reconcile retries whatever failed.
+    #[tracing::instrument(skip_all, fields(project = %project.name, center))]
+    async fn on_update(&self, project: Project) -> Result<()> {
+        let id = project
+            .id
+            .clone()
+            .ok_or_else(|| SupervisorError::invalid("project without id"))?;
+
+        let Some(mut tenant) = self.known.lock().unwrap().get(&id.to_string()).cloned() else {
+            return Err(SupervisorError::not_found("project").record(&id));
+        };
+        tracing::Span::current().record("center", tenant.center.as_str());
+        check_state(&project)?;
+
+        let old = std::mem::replace(&mut tenant.project, project);
+        let Tenant { center, project } = &tenant;
+
+        if old.token != project.token {
+            self.rotate_token(center, &project.name, &old.token, &project.token)
+                .await?;
+        }
+
+        if old.state == ProjectState::Finished && project.state != ProjectState::Finished {
+            warn!(state = ?project.state, "A finished project cannot be reopened");
+        }
+
+        let mut failed = None;
+        for handler in &self.listeners {
+            let updated = handler.0.on_project_update(&old, project, center).await;
+
+            if let Err(error) = updated {
+                error!(%error);
+                failed.get_or_insert(error);
+            }
+        }
+
+        if old.state != project.state {
+            if project.state == ProjectState::Finished {
+                self.freeze(center, &project.name)
                     .await
-                    .map_err(|_| "Failed to get center")?;
-                let center = center.unwrap();
-
-                self.execute_migrations(&center.name, &project.name);
-
-                // {{{ info for sc user
-                let sql = format!(
-                    "
-                    USE NS {} DB {};
-                    DEFINE TOKEN user_scope ON SCOPE user TYPE HS256 VALUE '{}';
-                    ",
-                    &center.name, &project.name, &project.token
-                );
-
-                self.db.query(sql).await.unwrap();
-                // }}}
-
-                for handler in &self.listeners {
-                    handler
-                        .0
-                        .on_project_create(&project.name, &center.name)
-                        .await;
-                }
+                    .tenant(center, &project.name)?;
             }
-            surrealdb::Action::Update => {
-                for handler in &self.listeners {
-                    handler.0.on_project_update(&project.name).await;
+
+            info!(from = ?old.state, to = ?project.state, "Project state changed");
+        }
+
+        if let Some(error) = failed {
+            return Err(error);
+        }
+
+        self.known.lock().unwrap().insert(id.to_string(), tenant);
+
+        Ok(())
+    }
+
+    async fn define_token(
+        &self,
+        center: &str,
+        project: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<()> {
+        // the secret is project data, it is written as an escaped string
+        let sql = format!(
+            "DEFINE TOKEN {name} ON SCOPE user TYPE HS256 VALUE {};",
+            Strand::from(token)
+        );
+
+        Session::new(&self.db, center, project)
+            .execute(&sql)
+            .await
+            .tenant(center, project)
+    }
+
+    /// Redefines the scope token from the project record and removes the
+    /// previous one. A restart cuts the grace period of a rotation short, and
+    /// a token changed while the supervisor was down is only seen here.
+    async fn restore_token(&self, center: &str, project: &str, token: &str) -> Result<()> {
+        self.define_token(center, project, SCOPE_TOKEN, token)
+            .await?;
+
+        if self
+            .remove_tokens(center, project, &[PREVIOUS_SCOPE_TOKEN])
+            .await?
+        {
+            info!("Previous scope token removed");
+        }
+
+        Ok(())
+    }
+
+    /// Removes both scope tokens, nobody can sign in to the project until
+    /// [`ProjectsManager::restore_token`] defines them again.
+    async fn revoke_tokens(&self, center: &str, project: &str) -> Result<()> {
+        self.remove_tokens(center, project, &[SCOPE_TOKEN, PREVIOUS_SCOPE_TOKEN])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes whichever of `names` the scope has, `true` when there was any.
+    async fn remove_tokens(&self, center: &str, project: &str, names: &[&str]) -> Result<bool> {
+        let session = Session::new(&self.db, center, project);
+        let mut res = session
+            .query("INFO FOR SCOPE user;")
+            .await
+            .tenant(center, project)?;
+        let info: Option<ScopeInfo> = res.take(res.num_statements() - 1).tenant(center, project)?;
+        let tokens = info.map(|info| info.tokens).unwrap_or_default();
+
+        // REMOVE TOKEN fails on a token that is not there
+        let sql: String = names
+            .iter()
+            .filter(|name| tokens.contains_key(**name))
+            .map(|name| format!("REMOVE TOKEN {name} ON SCOPE user;\n"))
+            .collect();
+
+        if sql.is_empty() {
+            return Ok(false);
+        }
+
+        session.execute(&sql).await.tenant(center, project)?;
+
+        Ok(true)
+    }
+
+    /// Signs users in with the new secret only, or with both until the grace
+    /// period ends. Shutting down ends it early, the previous secret must not
+    /// outlive the process that would remove it.
+    async fn rotate_token(&self, center: &str, project: &str, old: &str, new: &str) -> Result<()> {
+        let key = format!("{center}/{project}");
+        let grace = Duration::from_secs(self.config.scope.token_grace);
+
+        // a newer rotation takes over the grace period of the one before
+        if let Some(previous) = self.token_grace.lock().unwrap().remove(&key) {
+            previous.trigger();
+        }
+
+        if !grace.is_zero() {
+            self.define_token(center, project, PREVIOUS_SCOPE_TOKEN, old)
+                .await?;
+        }
+        self.define_token(center, project, SCOPE_TOKEN, new).await?;
+
+        info!(grace = grace.as_secs(), "Scope token rotated");
+
+        if grace.is_zero() {
+            return Ok(());
+        }
+
+        let stop = self.shutdown.child();
+        self.token_grace.lock().unwrap().insert(key, stop.clone());
+
+        let shutdown = self.shutdown.clone();
+        let session = Session::new(&self.db, center, project);
+        let sql = format!("REMOVE TOKEN {PREVIOUS_SCOPE_TOKEN} ON SCOPE user;");
+
+        self.shutdown.spawn(
+            async move {
+                let expired = tokio::select! {
+                    _ = tokio::time::sleep(grace) => true,
+                    // superseded or deleted, unless the whole process stops
+                    _ = stop.triggered() => shutdown.is_triggered(),
+                };
+
+                if !expired {
+                    return;
+                }
+
+                match session.execute(&sql).await {
+                    Ok(()) => info!("Previous scope token removed"),
+                    Err(error) => error!(%error, "Failed to remove the previous scope token"),
                 }
             }
-            surrealdb::Action::Delete => {
-                // TODO: backup project data
+            .instrument(tracing::Span::current()),
+        );
+
+        Ok(())
+    }
 
-                for handler in &self.listeners {
-                    handler.0.on_project_delete(&project.name).await;
+    /// Takes every write permission away from the project tables. Users only
+    /// reach the database through the scope, the supervisor signs in as root
+    /// and is not affected.
+    async fn freeze(&self, center: &str, project: &str) -> Result<()> {
+        let session = Session::new(&self.db, center, project);
+        let mut res = session.query("INFO FOR DB;").await?;
+        let info: Option<DbInfo> = res.take(res.num_statements() - 1)?;
+
+        let mut sql = String::new();
+        for definition in info.map(|info| info.tables).unwrap_or_default().values() {
+            let statements = surrealdb::sql::parse(definition)
+                .map_err(surrealdb::Error::from)
+                .query(definition)?;
+
+            for statement in statements {
+                if let Statement::Define(DefineStatement::Table(mut table)) = statement {
+                    table.permissions.create = Permission::None;
+                    table.permissions.update = Permission::None;
+                    table.permissions.delete = Permission::None;
+
+                    sql.push_str(&format!("{table};\n"));
                 }
             }
-            _ => println!("Action not supported"),
+        }
+
+        if !sql.is_empty() {
+            session.execute(&sql).await.query(&sql)?;
         }
 
         Ok(())
     }
 
-    fn execute_migrations(&self, center_name: impl Into<String>, project_name: impl Into<String>) {
-        // TODO:
-        // - url
-        // - credentials
+    /// Archives the project database and records where, then lets the
+    /// listeners release what they hold for the project. A failed backup is
+    /// audited too, and neither a failed backup nor a failed audit keeps the
+    /// project alive.
+    #[tracing::instrument(skip_all, fields(center = %tenant.center, project = %tenant.project.name))]
+    async fn on_delete(&self, tenant: Tenant) -> Result<()> {
+        let archive = backup(&self.config, &tenant.center, &tenant.project.name)
+            .await
+            .tenant(&tenant.center, &tenant.project.name);
 
-        let center_name = center_name.into();
-        let project_name = project_name.into();
-        // let p_db = self.db.clone();
-        let db_url = self.db_url.clone();
-        let cred = self.cred.clone();
+        match &archive {
+            Ok(path) => info!(archive = %path.display(), "Project backed up"),
+            Err(error) => error!(%error, "Failed to back up project"),
+        }
 
-        tokio::spawn(async move {
-            let db = Surreal::new::<Http>(db_url).await.unwrap();
-            db.signin(Root {
-                username: cred.user.as_str(),
-                password: cred.pass.as_str(),
-            })
+        // the project is gone either way, its streams and user must go too
+        if let Err(error) = self.audit(&tenant, &archive).await {
+            error!(%error, "Failed to audit the deletion");
+        }
+
+        let key = format!("{}/{}", tenant.center, tenant.project.name);
+        if let Some(grace) = self.token_grace.lock().unwrap().remove(&key) {
+            grace.trigger();
+        }
+
+        for handler in &self.listeners {
+            let deleted = handler
+                .0
+                .on_project_delete(&tenant.project.name, &tenant.center)
+                .await;
+
+            if let Err(error) = deleted {
+                error!(%error);
+            }
+        }
+
+        if let Err(error) =
+            connection::release(&self.db, &tenant.center, &tenant.project.name).await
+        {
+            error!(%error, "Failed to remove the database user");
+        }
+
+        info!("Project deleted");
+
+        Ok(())
+    }
+
+    async fn audit(&self, tenant: &Tenant, archive: &Result<PathBuf>) -> Result<()> {
+        let (path, failure) = match archive {
+            Ok(path) => (Some(path.display().to_string()), None),
+            Err(error) => (None, Some(error.to_string())),
+        };
+
+        let sql = format!(
+            r#"
+            CREATE {} SET
+                action = "project_deleted",
+                center = $b_center,
+                project = $b_project,
+                archive = $b_archive,
+                error = $b_error,
+                created = time::now();
+            "#,
+            self.config.tables.audit
+        );
+
+        self.main
+            .query(&sql)
+            .bind(("b_center", &tenant.center))
+            .bind(("b_project", &tenant.project.name))
+            .bind(("b_archive", path))
+            .bind(("b_error", failure))
             .await
-            .unwrap();
+            .query(&sql)?
+            .check()
+            .query(&sql)?;
+
+        Ok(())
+    }
 
-            db.use_ns("global").use_db("interventions").await.unwrap();
-            let mut backup = db.export(()).await.unwrap();
+    /// Imports the template into a new project database, then applies the
+    /// migrations the template did not carry yet. A failure drops the half
+    /// built database and is recorded on the project.
+    #[tracing::instrument(name = "migrate", skip_all)]
+    async fn execute_migrations(&self, center: &str, project: &str) -> Result<()> {
+        let migrated = async {
+            migrate(&self.config, center, project).await?;
+            self.migrations.apply(center, project).await
+        }
+        .await
+        .tenant(center, project);
 
-            let mut buffer = Vec::new();
-            while let Some(result) = backup.next().await {
-                if let Ok(data) = result {
-                    buffer.extend_from_slice(&data);
+        match migrated {
+            Ok(_) => {
+                if let Err(error) = self.migrations.record(center, project, None).await {
+                    error!(%error, "Failed to record the migration");
                 }
+
+                Ok(())
             }
+            Err(error) => {
+                self.migrations.discard(center, project, &error).await;
 
-            let dir_name = format!("temp-{}_{}", center_name, project_name);
+                Err(error)
+            }
+        }
+    }
+}
 
-            // create a temp dir
-            let dir = TempDir::new(&dir_name).unwrap();
-            let path = dir.path().join("dump.surql");
+/// A renamed center takes its projects along, they are handed to the
+/// listeners again under the new namespace. While the databases move the
+/// scope tokens are revoked, a write made meanwhile would stay behind in the
+/// old namespace.
+#[async_trait::async_trait]
+impl CentersManagerTrait for ProjectsManager {
+    async fn on_center_detach(&self, center: &str) -> Result<()> {
+        let tenants: Vec<Tenant> = self
+            .known
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|tenant| tenant.center == center)
+            .cloned()
+            .collect();
+
+        for Tenant { center, project } in tenants {
+            let key = format!("{center}/{}", project.name);
+            if let Some(grace) = self.token_grace.lock().unwrap().remove(&key) {
+                grace.trigger();
+            }
 
-            // save the buffer into a file
-            let mut file = tokio::fs::File::create(&path).await.unwrap();
-            file.write_all(&buffer).await.unwrap();
+            for handler in &self.listeners {
+                if let Err(error) = handler.0.on_project_delete(&project.name, &center).await {
+                    error!(%error);
+                }
+            }
 
-            // import the migration
-            db.use_ns(center_name).use_db(project_name).await.unwrap();
-            db.import(path).await.unwrap();
+            // the user would be copied along, the new database gets its own
+            if let Err(error) = connection::release(&self.db, &center, &project.name).await {
+                error!(%error, "Failed to remove the database user");
+            }
 
-            // cleanup
-            // drop(dir);
-            dir.close().unwrap();
-        });
+            if let Err(error) = self.revoke_tokens(&center, &project.name).await {
+                error!(%error, project = %project.name, "Failed to revoke the scope tokens");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn on_center_attach(&self, old: &str, new: &str) -> Result<()> {
+        let tenants: Vec<Tenant> = self
+            .known
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter(|tenant| tenant.center == old)
+            .map(|tenant| {
+                tenant.center = new.to_string();
+                tenant.clone()
+            })
+            .collect();
+
+        for Tenant { center, project } in tenants {
+            if let Err(error) = self
+                .restore_token(&center, &project.name, &project.token)
+                .await
+            {
+                error!(%error, project = %project.name, "Failed to restore the scope token");
+            }
+
+            for handler in &self.listeners {
+                if let Err(error) = handler.0.on_init(&project, &center).await {
+                    error!(%error);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Refuses a project whose center or own name cannot serve as namespace,
+/// database and backup directory.
+fn check_names(project: &Project, center: &Center) -> Result<()> {
+    Ident::new(center.name.as_str())?;
+    Ident::new(project.name.as_str())?;
+
+    Ok(())
+}
+
+/// Refuses a project in a state the supervisor does not know, a typo must
+/// not be taken for any of them.
+fn check_state(project: &Project) -> Result<()> {
+    match &project.state {
+        ProjectState::Unknown(state) => Err(SupervisorError::invalid(format!(
+            "unknown state {state:?}, project left as it is"
+        ))),
+        _ => Ok(()),
     }
 }
 
+/// Imports the template into `center/project`.
+async fn migrate(config: &Config, center_name: &str, project_name: &str) -> Result<()> {
+    let db = connection::exclusive(config).await?;
+    let global = config.namespaces.global.as_str();
+    let template = config.namespaces.template.as_str();
+
+    let buffer = migrations::dump(&db, global, template).await?;
+
+    migrations::import(&db, center_name, project_name, &buffer).await
+}
+
+/// Writes a gzipped export of `center/project` into the backup directory.
+async fn backup(config: &Config, center: &str, project: &str) -> Result<PathBuf> {
+    let buffer = {
+        let db = connection::exclusive(config).await?;
+        migrations::dump(&db, center, project).await?
+    };
+
+    let dir = config.backup.dir.join(center);
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let path = dir.join(format!("{project}-{stamp}.surql.gz"));
+    let archive = path.clone();
+
+    tokio::task::spawn_blocking(move || -> std::io::Result<()> {
+        let mut encoder = GzEncoder::new(std::fs::File::create(archive)?, Compression::default());
+        encoder.write_all(&buffer)?;
+        encoder.finish()?.sync_all()
+    })
+    .await
+    // a panicking encoder fails the backup, not the supervisor
+    .map_err(std::io::Error::other)??;
+
+    Ok(path)
+}
+
 #[async_trait::async_trait]
 pub trait ProjectsManagerTrait: Send + Sync + 'static {
-    async fn on_init(&self, project: &str, center: &str);
-    async fn on_project_create(&self, project: &str, center: &str);
-    async fn on_project_update(&self, project: &str);
-    async fn on_project_delete(&self, project: &str);
+    async fn on_init(&self, project: &Project, center: &str) -> Result<()>;
+    async fn on_project_create(&self, project: &str, center: &str) -> Result<()>;
+    async fn on_project_update(&self, old: &Project, new: &Project, center: &str) -> Result<()>;
+    async fn on_project_delete(&self, project: &str, center: &str) -> Result<()>;
+
+    async fn on_shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,480 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use serde::Deserialize;
+use surrealdb::engine::any::Any;
+use surrealdb::error::Db;
+use surrealdb::Surreal;
+use tempdir::TempDir;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::connection::{self, Session};
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::ident::Ident;
+use crate::metrics;
+
+/// The migrations the supervisor itself relies on, shipped first in the
+/// repository's `migrations` directory. They reach every project at startup,
+/// whatever `rollout.on_start` holds back.
+const OWN: [(u32, &str); 2] = [(1, "state_markers"), (2, "standby_marker")];
+
+/// One ordered schema change. Every database it was applied to keeps a copy
+/// in its migrations table, so the template can serve as the source.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct Migration {
+    pub version: u32,
+    pub name: String,
+    pub script: String,
+}
+
+impl Migration {
+    fn is_own(&self) -> bool {
+        OWN.contains(&(self.version, self.name.as_str()))
+    }
+}
+
+/// Applies the versioned migrations to the template and to every project
+/// database, which all track what they already received.
+#[derive(Clone)]
+pub struct Migrations {
+    db: Surreal<Any>,
+    config: Arc<Config>,
+}
+
+impl Migrations {
+    pub fn new(db: Surreal<Any>, config: Arc<Config>) -> Self {
+        Self { db, config }
+    }
+
+    /// Every migration in version order, read from `migrations.dir` when set
+    /// and from the template database otherwise.
+    pub async fn available(&self) -> Result<Vec<Migration>> {
+        match &self.config.migrations.dir {
+            Some(dir) => read_dir(dir).await,
+            None => {
+                let namespaces = &self.config.namespaces;
+
+                self.applied(&namespaces.global, &namespaces.template).await
+            }
+        }
+    }
+
+    /// Migrations recorded in `ns/database`, in version order.
+    pub async fn applied(&self, ns: &str, database: &str) -> Result<Vec<Migration>> {
+        let sql = format!(
+            "SELECT version, name, script FROM {} ORDER BY version;",
+            self.config.tables.migrations
+        );
+        let mut res = Session::new(&self.db, ns, database)
+            .query(&sql)
+            .await
+            .query(&sql)
+            .tenant(ns, database)?;
+
+        res.take(res.num_statements() - 1)
+            .query(&sql)
+            .tenant(ns, database)
+    }
+
+    pub async fn pending(&self, ns: &str, database: &str) -> Result<Vec<Migration>> {
+        let applied: BTreeSet<u32> = self
+            .applied(ns, database)
+            .await?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect();
+
+        let mut available = self.available().await?;
+        available.retain(|migration| !applied.contains(&migration.version));
+
+        Ok(available)
+    }
+
+    /// Publishes how many migrations `ns/database` is missing.
+    pub async fn report(&self, ns: &str, database: &str) -> Result<usize> {
+        let pending = self.pending(ns, database).await?.len();
+        self.behind(ns, database, pending);
+
+        Ok(pending)
+    }
+
+    /// Applies the pending migrations of `ns/database` in order, each in one
+    /// transaction with its record, stopping at the first that fails. Every
+    /// statement of a script ends with a semicolon.
+    pub async fn apply(&self, ns: &str, database: &str) -> Result<usize> {
+        let pending = self.pending(ns, database).await?;
+
+        self.apply_pending(ns, database, pending, 0).await
+    }
+
+    /// Like [`Migrations::apply`], only for the supervisor's own migrations
+    /// that come before any other pending one. A failure is recorded on the
+    /// project and counted.
+    pub async fn apply_own(&self, center: &str, project: &str) -> Result<usize> {
+        let mut pending = self.pending(center, project).await?;
+        let own = pending.iter().take_while(|m| m.is_own()).count();
+        let others = pending.split_off(own).len();
+
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let applied = self.apply_pending(center, project, pending, others).await;
+        match &applied {
+            Ok(_) => self.record(center, project, None).await?,
+            Err(error) => self.fail(center, project, error).await,
+        }
+
+        applied
+    }
+
+    /// Applies `pending`, `after` being how many migrations are still
+    /// pending once all of them are.
+    async fn apply_pending(
+        &self,
+        ns: &str,
+        database: &str,
+        pending: Vec<Migration>,
+        after: usize,
+    ) -> Result<usize> {
+        let session = Session::new(&self.db, ns, database);
+
+        for (applied, migration) in pending.iter().enumerate() {
+            let sql = format!(
+                r#"
+                BEGIN TRANSACTION;
+                {script}
+                CREATE type::thing($b_table, $b_version) SET
+                    version = $b_version,
+                    name = $b_name,
+                    script = $b_script,
+                    applied = time::now();
+                COMMIT TRANSACTION;
+                "#,
+                script = migration.script,
+            );
+
+            if let Err(error) = self.run(&session, &sql, migration).await {
+                self.behind(ns, database, after + pending.len() - applied);
+
+                return Err(error.tenant(ns, database));
+            }
+
+            info!(version = migration.version, name = %migration.name, "Migration applied");
+        }
+
+        self.behind(ns, database, after);
+
+        Ok(pending.len())
+    }
+
+    async fn run(&self, session: &Session, sql: &str, migration: &Migration) -> Result<()> {
+        session
+            .query(sql)
+            .bind(("b_table", &self.config.tables.migrations))
+            .bind(("b_version", migration.version))
+            .bind(("b_name", &migration.name))
+            .bind(("b_script", &migration.script))
+            .await
+            .query(&migration.script)?
+            .check()
+            .query(&migration.script)?;
+
+        Ok(())
+    }
+
+    /// Applies the pending migrations of `center/project` and runs `checks`,
+    /// all in one transaction. When any of that fails nothing is left behind,
+    /// and the failure is recorded on the project and counted.
+    ///
+    /// Nothing is dropped or imported, so writes made meanwhile and the live
+    /// queries on the database survive a failure.
+    pub async fn apply_checked(
+        &self,
+        center: &str,
+        project: &str,
+        checks: &[String],
+    ) -> Result<usize> {
+        let pending = self.pending(center, project).await?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut sql = String::from("BEGIN TRANSACTION;\n");
+        for (n, migration) in pending.iter().enumerate() {
+            sql.push_str(&format!(
+                r#"
+                {script}
+                CREATE type::thing($b_table, $b_version_{n}) SET
+                    version = $b_version_{n},
+                    name = $b_name_{n},
+                    script = $b_script_{n},
+                    applied = time::now();
+                "#,
+                script = migration.script,
+            ));
+        }
+        for check in checks {
+            sql.push_str(check);
+            sql.push('\n');
+        }
+        sql.push_str("COMMIT TRANSACTION;");
+
+        let session = Session::new(&self.db, center, project);
+        let mut query = session
+            .query(&sql)
+            .bind(("b_table", &self.config.tables.migrations));
+        for (n, migration) in pending.iter().enumerate() {
+            query = query
+                .bind((format!("b_version_{n}"), migration.version))
+                .bind((format!("b_name_{n}"), &migration.name))
+                .bind((format!("b_script_{n}"), &migration.script));
+        }
+
+        let upgraded = match query.await {
+            Ok(mut res) => match failure(res.take_errors()) {
+                Some(error) => Err(error),
+                None => Ok(()),
+            },
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = upgraded {
+            let error = SupervisorError::from(error).tenant(center, project);
+            self.behind(center, project, pending.len());
+            warn!("Migrations rolled back");
+            self.fail(center, project, &error).await;
+
+            return Err(error);
+        }
+
+        for migration in &pending {
+            info!(version = migration.version, name = %migration.name, "Migration applied");
+        }
+        self.behind(center, project, 0);
+        self.record(center, project, None).await?;
+
+        Ok(pending.len())
+    }
+
+    /// Drops the database of a project whose creation failed half way and
+    /// records the failure on the project.
+    pub async fn discard(&self, center: &str, project: &str, error: &SupervisorError) {
+        let removed = async {
+            let db = connection::exclusive(&self.config).await?;
+            remove(&db, center, project).await
+        }
+        .await;
+
+        match removed {
+            Ok(()) => warn!("Removed the half built database"),
+            Err(error) => error!(%error, "Failed to remove the half built database"),
+        }
+
+        self.fail(center, project, error).await;
+    }
+
+    async fn fail(&self, center: &str, project: &str, error: &SupervisorError) {
+        metrics::MIGRATION_FAILURES
+            .with_label_values(&[center, project])
+            .inc();
+
+        if let Err(error) = self.record(center, project, Some(error)).await {
+            error!(%error, "Failed to record the migration failure");
+        }
+    }
+
+    /// Keeps the outcome of the last migration on the `projects` record,
+    /// `migration_status` is `ok` or `failed` with its `migration_error`.
+    pub async fn record(
+        &self,
+        center: &str,
+        project: &str,
+        error: Option<&SupervisorError>,
+    ) -> Result<()> {
+        let sql = format!(
+            r#"
+            UPDATE {} SET
+                migration_status = $b_status,
+                migration_error = $b_error,
+                migrated_at = time::now()
+            WHERE name = $b_project AND center.name = $b_center;
+            "#,
+            self.config.tables.projects
+        );
+        let namespaces = &self.config.namespaces;
+
+        Session::new(&self.db, &namespaces.global, &namespaces.main)
+            .query(&sql)
+            .bind(("b_status", if error.is_some() { "failed" } else { "ok" }))
+            .bind(("b_error", error.map(ToString::to_string)))
+            .bind(("b_center", center))
+            .bind(("b_project", project))
+            .await
+            .query(&sql)?
+            .check()
+            .query(&sql)?;
+
+        Ok(())
+    }
+
+    fn behind(&self, ns: &str, database: &str, pending: usize) {
+        metrics::MIGRATIONS_PENDING
+            .with_label_values(&[ns, database])
+            .set(pending as i64);
+
+        if pending > 0 {
+            warn!(pending, "Database is behind the migrations");
+        }
+    }
+}
+
+/// The error that failed a transaction. Its other statements only report
+/// that they were not executed, so those come last.
+fn failure(errors: HashMap<usize, surrealdb::Error>) -> Option<surrealdb::Error> {
+    errors
+        .into_iter()
+        .min_by_key(|(n, error)| (not_executed(error), *n))
+        .map(|(_, error)| error)
+}
+
+fn not_executed(error: &surrealdb::Error) -> bool {
+    matches!(
+        error,
+        surrealdb::Error::Db(
+            Db::QueryNotExecuted | Db::QueryNotExecutedDetail { .. } | Db::QueryCancelled
+        )
+    )
+}
+
+/// Exports a whole database through a client that may switch its session.
+pub(crate) async fn dump(db: &Surreal<Any>, ns: &str, database: &str) -> Result<Vec<u8>> {
+    db.use_ns(ns).use_db(database).await?;
+    let mut export = db.export(()).await?;
+
+    let mut buffer = Vec::new();
+    while let Some(result) = export.next().await {
+        buffer.extend_from_slice(&result?);
+    }
+
+    Ok(buffer)
+}
+
+/// Imports an export into `ns/database`, the client only reads files.
+pub(crate) async fn import(
+    db: &Surreal<Any>,
+    ns: &str,
+    database: &str,
+    buffer: &[u8],
+) -> Result<()> {
+    let dir = TempDir::new("import")?;
+    let path = dir.path().join("dump.surql");
+
+    let mut file = tokio::fs::File::create(&path).await?;
+    file.write_all(buffer).await?;
+
+    db.use_ns(ns).use_db(database).await?;
+    db.import(path).await?;
+
+    dir.close()?;
+
+    Ok(())
+}
+
+async fn remove(db: &Surreal<Any>, ns: &str, database: &str) -> Result<()> {
+    let sql = format!("REMOVE DATABASE {};", Ident::from(database));
+
+    db.use_ns(ns).use_db(database).await?;
+    db.query(&sql).await.query(&sql)?.check().query(&sql)?;
+
+    Ok(())
+}
+
+/// Reads the `<version>_<name>.surql` files of `dir`, other files are
+/// ignored.
+async fn read_dir(dir: &Path) -> Result<Vec<Migration>> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut migrations = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        let Some(stem) = file_name.strip_suffix(".surql") else {
+            continue;
+        };
+
+        let (version, name) = parse_stem(stem).ok_or_else(|| {
+            SupervisorError::invalid(format!(
+                "migration {file_name} is not named <version>_<name>.surql"
+            ))
+        })?;
+
+        migrations.push(Migration {
+            version,
+            name: name.to_string(),
+            script: tokio::fs::read_to_string(entry.path()).await?,
+        });
+    }
+
+    migrations.sort_by_key(|migration| migration.version);
+
+    if let Some(twins) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        return Err(SupervisorError::invalid(format!(
+            "migrations {} and {} share version {}",
+            twins[0].name, twins[1].name, twins[0].version
+        )));
+    }
+
+    Ok(migrations)
+}
+
+fn parse_stem(stem: &str) -> Option<(u32, &str)> {
+    let (version, name) = stem.split_once('_')?;
+
+    if name.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((version.parse().ok()?, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_carry_version_and_name() {
+        assert_eq!(parse_stem("0001_init"), Some((1, "init")));
+        assert_eq!(parse_stem("12_on_cron_v2"), Some((12, "on_cron_v2")));
+
+        assert_eq!(parse_stem("init"), None);
+        assert_eq!(parse_stem("v1_init"), None);
+        assert_eq!(parse_stem("+1_init"), None);
+        assert_eq!(parse_stem("0002_"), None);
+    }
+
+    #[tokio::test]
+    async fn directory_is_read_in_version_order() {
+        let dir = tempdir::TempDir::new("migrations").unwrap();
+        for (file, content) in [
+            ("0010_later.surql", "DEFINE TABLE later;"),
+            ("0002_first.surql", "DEFINE TABLE first;"),
+            ("README.md", "not a migration"),
+        ] {
+            std::fs::write(dir.path().join(file), content).unwrap();
+        }
+
+        let migrations = read_dir(dir.path()).await.unwrap();
+        let versions: Vec<_> = migrations.iter().map(|m| (m.version, &*m.name)).collect();
+        assert_eq!(versions, [(2, "first"), (10, "later")]);
+
+        std::fs::write(dir.path().join("0002_again.surql"), "").unwrap();
+        assert!(read_dir(dir.path()).await.is_err());
+    }
+}
@@ -1,3 +1,6 @@
+pub mod drift;
 mod events;
 mod interv_users;
 pub mod manager;
+pub mod migrations;
+pub mod rollout;
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use surrealdb::engine::any::Any;
+use surrealdb::Surreal;
+use tracing::{error, info, info_span, Instrument};
+
+use crate::config::{self, Config, OnStart};
+use crate::connection::Session;
+use crate::error::{Result, ResultExt, SupervisorError};
+use crate::metrics;
+use crate::models::project::{ProjectState, ProjectWithCenter};
+
+use super::migrations::Migrations;
+
+/// A project database a rollout migrates.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Target {
+    pub center: String,
+    pub project: String,
+}
+
+/// What a rollout did, wave by wave.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub migrated: Vec<Target>,
+    /// The projects of the last wave that failed their migration or their
+    /// checks, empty unless the rollout halted.
+    pub halted: Vec<(Target, SupervisorError)>,
+    /// The waves left behind by the halt.
+    pub remaining: Vec<Target>,
+}
+
+/// Pushes pending migrations to the existing projects in waves, the
+/// canaries first. The projects of a wave are migrated together and the
+/// next wave only starts once every one of them passed and `rollout.soak`
+/// went by.
+#[derive(Clone)]
+pub struct Rollout {
+    db: Surreal<Any>,
+    config: Arc<Config>,
+    migrations: Migrations,
+}
+
+impl Rollout {
+    pub fn new(db: Surreal<Any>, config: Arc<Config>) -> Self {
+        let migrations = Migrations::new(db.clone(), config.clone());
+
+        Self {
+            db,
+            config,
+            migrations,
+        }
+    }
+
+    /// Migrates the template when the migrations come from a directory, then
+    /// every project that is not finished. Finished projects are only
+    /// reported, they stay read-only.
+    pub async fn run(&self) -> Result<Report> {
+        let targets = self.targets().await?;
+
+        Ok(self.roll_out(self.waves(targets)).await)
+    }
+
+    /// What the supervisor rolls out when it starts, see `rollout.on_start`.
+    /// The projects it holds back still get the supervisor's own migrations,
+    /// it cannot work without them.
+    pub async fn on_start(&self) -> Result<Report> {
+        let targets = self.targets().await?;
+        let limit = on_start_waves(&self.config.rollout);
+
+        if limit < usize::MAX {
+            self.apply_own(&targets).await;
+        }
+
+        let waves = self.waves(targets).into_iter().take(limit).collect();
+
+        Ok(self.roll_out(waves).await)
+    }
+
+    /// Migrates the template, then lists the projects to roll out to.
+    async fn targets(&self) -> Result<Vec<Target>> {
+        let namespaces = &self.config.namespaces;
+
+        if self.config.migrations.dir.is_some() {
+            let span = info_span!(
+                "migrate",
+                center = %namespaces.global,
+                project = %namespaces.template,
+            );
+
+            self.migrations
+                .apply(&namespaces.global, &namespaces.template)
+                .instrument(span)
+                .await?;
+        }
+
+        let sql = format!(
+            "SELECT * FROM {} FETCH center;",
+            self.config.tables.projects
+        );
+        let mut res = Session::new(&self.db, &namespaces.global, &namespaces.main)
+            .query(&sql)
+            .await
+            .query(&sql)?;
+        let projects: Vec<ProjectWithCenter> = res.take(res.num_statements() - 1).query(&sql)?;
+
+        let mut targets = Vec::new();
+        for project in projects {
+            if project.state == ProjectState::Finished {
+                self.migrations
+                    .report(&project.center.name, &project.name)
+                    .await?;
+            } else {
+                targets.push(Target {
+                    center: project.center.name,
+                    project: project.name,
+                });
+            }
+        }
+
+        Ok(targets)
+    }
+
+    fn waves(&self, targets: Vec<Target>) -> Vec<Vec<Target>> {
+        waves(
+            targets,
+            &self.config.rollout.canaries,
+            self.config.rollout.wave,
+        )
+    }
+
+    /// Applies the supervisor's own pending migrations to every target, a
+    /// failure only leaves that project behind.
+    async fn apply_own(&self, targets: &[Target]) {
+        let applied = targets.iter().map(|target| async move {
+            let span = info_span!("migrate", center = %target.center, project = %target.project);
+
+            self.migrations
+                .apply_own(&target.center, &target.project)
+                .instrument(span)
+                .await
+                .map_err(|error| (target, error))
+        });
+
+        for result in join_all(applied).await {
+            if let Err((target, error)) = result {
+                error!(%error, project = %target, "Migration failed");
+            }
+        }
+    }
+
+    async fn roll_out(&self, waves: Vec<Vec<Target>>) -> Report {
+        let soak = Duration::from_secs(self.config.rollout.soak);
+        let mut report = Report::default();
+        let mut waves = waves.into_iter().enumerate().peekable();
+
+        while let Some((wave, targets)) = waves.next() {
+            let upgrades = targets.into_iter().map(|target| async move {
+                let span =
+                    info_span!("migrate", center = %target.center, project = %target.project);
+                let upgraded = self.upgrade(&target).instrument(span).await;
+
+                (target, upgraded)
+            });
+
+            for (target, upgraded) in join_all(upgrades).await {
+                match upgraded {
+                    Ok(0) => {}
+                    Ok(_) => report.migrated.push(target),
+                    Err(error) => {
+                        error!(%error, project = %target, "Migration failed");
+                        report.halted.push((target, error));
+                    }
+                }
+            }
+
+            if !report.halted.is_empty() {
+                report.remaining = waves.flat_map(|(_, wave)| wave).collect();
+                error!(
+                    wave,
+                    failed = report.halted.len(),
+                    remaining = report.remaining.len(),
+                    "Rollout halted"
+                );
+                metrics::ROLLOUT_HALTED.set(1);
+
+                return report;
+            }
+
+            info!(wave, "Wave rolled out");
+
+            if waves.peek().is_some() && !soak.is_zero() {
+                info!(wave, soak = soak.as_secs(), "Soaking before the next wave");
+                tokio::time::sleep(soak).await;
+            }
+        }
+
+        metrics::ROLLOUT_HALTED.set(0);
+
+        report
+    }
+
+    /// Applies the pending migrations of one project and, when there were
+    /// any, runs the configured checks against it. A failure puts the
+    /// project back as it was before.
+    async fn upgrade(&self, target: &Target) -> Result<usize> {
+        self.migrations
+            .apply_checked(&target.center, &target.project, &self.config.rollout.checks)
+            .await
+    }
+}
+
+/// How many waves the rollout at startup goes through. Without canaries
+/// there is nothing to stage, so `canaries` migrates every wave.
+fn on_start_waves(rollout: &config::Rollout) -> usize {
+    match rollout.on_start {
+        OnStart::Off => 0,
+        OnStart::Canaries if rollout.canaries.is_empty() => usize::MAX,
+        OnStart::Canaries => 1,
+        OnStart::All => usize::MAX,
+    }
+}
+
+/// The canaries found among `targets` in their configured order, then the
+/// rest by center and project, `size` at a time or all at once for `0`.
+fn waves(mut targets: Vec<Target>, canaries: &[String], size: usize) -> Vec<Vec<Target>> {
+    targets.sort();
+
+    let mut first = Vec::new();
+    for canary in canaries {
+        if let Some(at) = targets
+            .iter()
+            .position(|target| target.to_string() == *canary)
+        {
+            first.push(targets.remove(at));
+        }
+    }
+
+    let mut waves = Vec::new();
+    if !first.is_empty() {
+        waves.push(first);
+    }
+
+    if size == 0 {
+        waves.push(targets);
+    } else {
+        waves.extend(targets.chunks(size).map(<[Target]>::to_vec));
+    }
+
+    waves.retain(|wave| !wave.is_empty());
+    waves
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.center, self.project)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str) -> Target {
+        let (center, project) = name.split_once('/').unwrap();
+
+        Target {
+            center: center.to_string(),
+            project: project.to_string(),
+        }
+    }
+
+    fn names(waves: Vec<Vec<Target>>) -> Vec<Vec<String>> {
+        waves
+            .into_iter()
+            .map(|wave| wave.iter().map(ToString::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn startup_without_canaries_migrates_every_wave() {
+        let mut rollout = config::Rollout::default();
+        assert_eq!(on_start_waves(&rollout), usize::MAX);
+
+        rollout.canaries = vec!["a/one".to_string()];
+        assert_eq!(on_start_waves(&rollout), 1);
+
+        rollout.on_start = OnStart::Off;
+        assert_eq!(on_start_waves(&rollout), 0);
+    }
+
+    #[test]
+    fn canaries_go_first_then_the_rest_in_waves() {
+        let targets: Vec<Target> = ["b/two", "a/one", "c/three", "a/four"]
+            .into_iter()
+            .map(target)
+            .collect();
+        let canaries = ["c/three".to_string(), "z/gone".to_string()];
+
+        assert_eq!(
+            names(waves(targets.clone(), &canaries, 2)),
+            [vec!["c/three"], vec!["a/four", "a/one"], vec!["b/two"]]
+        );
+        assert_eq!(
+            names(waves(targets, &[], 0)),
+            [vec!["a/four", "a/one", "b/two", "c/three"]]
+        );
+        assert!(waves(Vec::new(), &canaries, 2).is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::task_tracker::TrackedFuture;
+use tokio_util::task::TaskTracker;
+
+/// How long in-flight work gets to finish once shutdown was triggered.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Stops every manager at once and keeps track of the tasks that must finish
+/// before the process exits: tenant streams and running cron executions.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl Shutdown {
+    /// A shutdown of its own that is also triggered with this one, sharing
+    /// the tracked tasks.
+    pub fn child(&self) -> Self {
+        Self {
+            token: self.token.child_token(),
+            tasks: self.tasks.clone(),
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Makes `task` part of the work [`Shutdown::drain`] waits for, from now
+    /// until it completes or is dropped.
+    pub fn track<F: Future>(&self, task: F) -> TrackedFuture<F> {
+        self.tasks.track_future(task)
+    }
+
+    /// Waits for the tracked tasks, returns `false` if they did not finish
+    /// within `timeout`.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.tasks.close();
+
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Resolves on Ctrl+C or, on unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_waits_for_tracked_work() {
+        let shutdown = Shutdown::default();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+
+        tokio::spawn(shutdown.track(rx));
+
+        assert!(!shutdown.drain(Duration::from_millis(50)).await);
+
+        tx.send(()).unwrap();
+        assert!(shutdown.drain(Duration::from_secs(1)).await);
+    }
+
+    #[test]
+    fn child_stops_alone_or_with_its_parent() {
+        let parent = Shutdown::default();
+        let first = parent.child();
+        let second = parent.child();
+
+        first.trigger();
+        assert!(first.is_triggered());
+        assert!(!second.is_triggered() && !parent.is_triggered());
+
+        parent.trigger();
+        assert!(second.is_triggered());
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::BufReader;
+use std::path::Path;
+
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore};
+use rustls_pemfile::Item;
+
+use crate::config;
+use crate::error::{Result, SupervisorError};
+
+/// The client side of `db.tls`, `None` when nothing is configured and the
+/// SurrealDB client can keep its own defaults.
+pub fn client_config(tls: &config::Tls) -> Result<Option<ClientConfig>> {
+    if !tls.is_configured() {
+        return Ok(None);
+    }
+
+    let mut roots = RootCertStore::empty();
+    match &tls.ca {
+        Some(ca) => {
+            for cert in certificates(ca)? {
+                roots.add(&cert).map_err(|e| {
+                    SupervisorError::config(format!("Invalid CA in {}: {e}", ca.display()))
+                })?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        })),
+    }
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let config = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+            .map_err(|e| {
+                SupervisorError::config(format!(
+                    "Invalid client certificate {}: {e}",
+                    cert.display()
+                ))
+            })?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => {
+            return Err(SupervisorError::config(
+                "db.tls.cert and db.tls.key go together",
+            ))
+        }
+    };
+
+    Ok(Some(config))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<Item>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| SupervisorError::config(format!("Failed to read {}: {e}", path.display())))?;
+
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| SupervisorError::config(format!("Failed to parse {}: {e}", path.display())))
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let certs: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        return Err(SupervisorError::config(format!(
+            "No certificate in {}",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| SupervisorError::config(format!("No private key in {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rustls::server::AllowAnyAuthenticatedClient;
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::connection;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    /// A server with the self-signed test certificate that wants a client
+    /// certificate from the same CA. Its task ends with the outcome of the
+    /// first handshake.
+    async fn server() -> (u16, JoinHandle<std::io::Result<bool>>) {
+        let mut roots = RootCertStore::empty();
+        for cert in certificates(&fixture("ca.pem")).unwrap() {
+            roots.add(&cert).unwrap();
+        }
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            .with_single_cert(
+                certificates(&fixture("server.pem")).unwrap(),
+                private_key(&fixture("server.key")).unwrap(),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let stream = TlsAcceptor::from(Arc::new(config)).accept(stream).await?;
+
+            Ok(stream.get_ref().1.peer_certificates().is_some())
+        });
+
+        (port, task)
+    }
+
+    fn config(port: u16, ca: bool) -> Config {
+        let mut config = Config::default();
+        config.db.host = format!("localhost:{port}");
+        config.db.tls = config::Tls {
+            ca: ca.then(|| fixture("ca.pem")),
+            cert: Some(fixture("client.pem")),
+            key: Some(fixture("client.key")),
+            ..Default::default()
+        };
+
+        config
+    }
+
+    #[tokio::test]
+    async fn client_trusts_the_ca_and_shows_its_certificate() {
+        let (port, server) = server().await;
+
+        // the server speaks no SurrealDB, only the handshake matters
+        let _ = connection::exclusive(&config(port, true)).await;
+
+        let handshake = tokio::time::timeout(Duration::from_secs(10), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(handshake.unwrap(), "no client certificate");
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_use_the_same_certificates() {
+        let (port, server) = server().await;
+        let config = config(port, true);
+        assert!(config.endpoint().starts_with("wss://"));
+
+        // the server speaks no websocket either, only the handshake matters
+        let endpoint = connection::remote(&config, config.endpoint()).unwrap();
+        let _ = surrealdb::engine::any::connect(endpoint).await;
+
+        let handshake = tokio::time::timeout(Duration::from_secs(10), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(handshake.unwrap(), "no client certificate");
+    }
+
+    #[tokio::test]
+    async fn unknown_authority_is_refused() {
+        let (port, server) = server().await;
+
+        assert!(connection::exclusive(&config(port, false)).await.is_err());
+
+        let handshake = tokio::time::timeout(Duration::from_secs(10), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(handshake.is_err());
+    }
+
+    #[test]
+    fn certificate_needs_its_key() {
+        let tls = config::Tls {
+            cert: Some(fixture("client.pem")),
+            ..Default::default()
+        };
+        assert!(client_config(&tls).is_err());
+
+        assert!(client_config(&config::Tls::default()).unwrap().is_none());
+    }
+}
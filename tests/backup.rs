@@ -0,0 +1,63 @@
+mod common;
+
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use common::{eventually, take, Harness};
+
+#[derive(Debug, Deserialize)]
+struct Audit {
+    center: String,
+    project: String,
+    archive: Option<String>,
+    error: Option<String>,
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn deleted_project_is_archived_and_audited() {
+    let harness = Harness::start().await;
+    harness
+        .create_project("center_backup", "project_backup")
+        .await;
+
+    harness
+        .project("center_backup", "project_backup")
+        .execute("CREATE scores:kept SET score = 42;")
+        .await
+        .unwrap();
+
+    harness
+        .main()
+        .execute("DELETE projects:project_backup;")
+        .await
+        .unwrap();
+
+    let main = harness.main();
+    let audit: Audit = eventually("the audit record", || async {
+        take::<Option<Audit>>(&main, "SELECT * FROM ONLY audit LIMIT 1;")
+            .await
+            .flatten()
+    })
+    .await;
+
+    assert_eq!(audit.center, "center_backup");
+    assert_eq!(audit.project, "project_backup");
+    assert_eq!(audit.error, None);
+
+    let archive = audit.archive.unwrap();
+    assert!(archive.starts_with(
+        &*harness
+            .backups
+            .path()
+            .join("center_backup")
+            .to_string_lossy()
+    ));
+
+    let mut dump = String::new();
+    GzDecoder::new(std::fs::File::open(&archive).unwrap())
+        .read_to_string(&mut dump)
+        .unwrap();
+    assert!(dump.contains("scores:kept"));
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use q_api_super::metrics;
+use surrealdb::sql::Value;
+
+use common::{eventually, take, Harness};
+
+const PROJECT: &str = "project_moved";
+
+/// Whether `center` has a namespace, and an editor user in it.
+async fn namespace(harness: &Harness, center: &str) -> (bool, bool) {
+    let kv: Value = take(&harness.main(), "INFO FOR KV;").await.unwrap();
+    let defined = kv.pick(&["namespaces".into(), center.into()]).is_some();
+
+    let ns: Value = take(&harness.project(center, "main"), "INFO FOR NS;")
+        .await
+        .unwrap_or_default();
+    let editor = ns.pick(&["users".into(), "editor".into()]).is_some();
+
+    (defined, editor)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn renamed_center_takes_its_namespace_along() {
+    let harness =
+        Harness::start_with(|config| config.centers.editor_pass = "editor_secret".to_string())
+            .await;
+    harness.create_project("center_old", PROJECT).await;
+
+    eventually("the editor user", || async {
+        (namespace(&harness, "center_old").await == (true, true)).then_some(())
+    })
+    .await;
+
+    harness
+        .project("center_old", PROJECT)
+        .execute(
+            r#"
+            CREATE events:tick SET
+                active = true,
+                script = "tick",
+                schedule = "*/1 * * * * *";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let jobs = |center: &str| metrics::SCHEDULED_JOBS.with_label_values(&[center, PROJECT]);
+    eventually("the job", || async {
+        (jobs("center_old").get() == 1).then_some(())
+    })
+    .await;
+
+    // a center with projects cannot go away
+    let deleted = harness.main().execute("DELETE centers:center_old;").await;
+    assert!(deleted
+        .unwrap_err()
+        .to_string()
+        .contains("still has projects"));
+
+    let name: Option<String> = take(
+        &harness.main(),
+        "SELECT VALUE name FROM ONLY centers:center_old;",
+    )
+    .await
+    .flatten();
+    assert_eq!(name.as_deref(), Some("center_old"));
+
+    harness
+        .main()
+        .execute("UPDATE centers:center_old SET name = 'center_new';")
+        .await
+        .unwrap();
+
+    eventually("the job in the new namespace", || async {
+        (jobs("center_new").get() == 1 && jobs("center_old").get() == 0).then_some(())
+    })
+    .await;
+
+    assert_eq!(namespace(&harness, "center_new").await, (true, true));
+    assert!(!namespace(&harness, "center_old").await.0);
+
+    let moved = harness.project("center_new", PROJECT);
+    let events: Vec<String> = take(&moved, "SELECT VALUE script FROM events;")
+        .await
+        .unwrap();
+    assert_eq!(events, ["tick"]);
+
+    // revoked for the move, users sign in again once it is through
+    let scope: Value = eventually("the scope token", || async {
+        let scope: Value = take(&moved, "INFO FOR SCOPE user;").await?;
+
+        scope
+            .pick(&["tokens".into(), "user_scope".into()])
+            .is_some()
+            .then_some(scope)
+    })
+    .await;
+    assert!(scope
+        .pick(&["tokens".into(), "user_scope_previous".into()])
+        .is_none());
+
+    // the event keeps running where the project is now
+    let runs = || async {
+        take::<Vec<String>>(&moved, "SELECT VALUE script FROM cron_log;")
+            .await
+            .map_or(0, |runs| runs.len())
+    };
+    let before = runs().await;
+    eventually("a run in the new namespace", || async {
+        (runs().await > before).then_some(())
+    })
+    .await;
+}
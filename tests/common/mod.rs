@@ -0,0 +1,198 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use q_api_super::config::Config;
+use q_api_super::connection::{self, Session};
+use q_api_super::metrics;
+use q_api_super::modules::centers::manager::CentersManager;
+use q_api_super::modules::join::manager::JoinManager;
+use q_api_super::modules::projects::manager::ProjectsManager;
+use q_api_super::shutdown::Shutdown;
+use serde::de::DeserializeOwned;
+use surrealdb::engine::any::Any;
+use surrealdb::opt::QueryResult;
+use surrealdb::sql::Value;
+use surrealdb::Surreal;
+use tempdir::TempDir;
+use tokio::task::JoinHandle;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(20);
+
+/// Every manager running against an embedded engine seeded with the
+/// fixtures, the same way `main` wires them.
+///
+/// The embedded engine lives as long as the runtime that started it, so each
+/// test file starts the harness from a single test.
+pub struct Harness {
+    pub db: Surreal<Any>,
+    pub config: Arc<Config>,
+    shutdown: Shutdown,
+    projects: JoinHandle<()>,
+    /// Where deleted projects are archived, removed with the harness.
+    #[allow(dead_code)] // only the backup test reads it
+    pub backups: TempDir,
+}
+
+impl Harness {
+    #[allow(dead_code)] // not every test file keeps the defaults
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Like [`Harness::start`], letting `configure` adjust the configuration
+    /// before the managers read it.
+    pub async fn start_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Config::default();
+        config.db.host = "mem://".to_string();
+        config.embedded.template = Some(fixture("interventions.surql"));
+
+        let backups = TempDir::new("backups").unwrap();
+        config.backup.dir = backups.path().to_path_buf();
+        // short enough for the token test to see the grace period end
+        config.scope.token_grace = 1;
+        // the fields the supervisor relies on, as a deployment ships them
+        config.migrations.dir = Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("migrations"));
+        configure(&mut config);
+
+        let config = Arc::new(config);
+        let db = connection::connect(&config).await;
+
+        let shutdown = Shutdown::default();
+
+        let joins = JoinManager::new(config.clone(), shutdown.clone()).await;
+        tokio::spawn(async move {
+            let _ = joins.start().await;
+        });
+
+        let projects = Arc::new(ProjectsManager::new(config.clone(), shutdown.clone()).await);
+
+        let mut centers = CentersManager::new(config.clone(), shutdown.clone()).await;
+        centers.listen(projects.clone());
+        tokio::spawn(async move {
+            let _ = centers.start().await;
+        });
+
+        let projects = tokio::spawn(async move {
+            let _ = projects.start().await;
+        });
+
+        // let the live queries register before anything is written
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        Self {
+            db,
+            config,
+            shutdown,
+            projects,
+            backups,
+        }
+    }
+
+    /// Triggers shutdown and waits until the managers persisted their state.
+    #[allow(dead_code)] // only the shutdown test stops the harness
+    pub async fn stop(self) {
+        self.shutdown.trigger();
+
+        tokio::time::timeout(TIMEOUT, self.projects)
+            .await
+            .expect("timed out stopping the managers")
+            .unwrap();
+    }
+
+    pub fn main(&self) -> Session {
+        let namespaces = &self.config.namespaces;
+
+        Session::new(&self.db, &namespaces.global, &namespaces.main)
+    }
+
+    pub fn project(&self, center: &str, project: &str) -> Session {
+        Session::new(&self.db, center, project)
+    }
+
+    /// Creates a center with one project and waits until the template was
+    /// imported into the project database and its streams are subscribed.
+    #[allow(dead_code)] // the startup test seeds its projects instead
+    pub async fn create_project(&self, center: &str, project: &str) {
+        self.main()
+            .query(
+                r#"
+                CREATE type::thing("centers", $b_center) SET name = $b_center;
+                CREATE type::thing("projects", $b_project) SET
+                    name = $b_project,
+                    center = type::thing("centers", $b_center),
+                    state = "active",
+                    token = "secret";
+                "#,
+            )
+            .bind(("b_center", center))
+            .bind(("b_project", project))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let session = self.project(center, project);
+        eventually("template imported", || async {
+            let info: Value = take(&session, "INFO FOR DB;").await?;
+
+            info.pick(&["functions".into(), "on_cron".into()])
+                .is_some()
+                .then_some(())
+        })
+        .await;
+
+        // notifications sent before the tenant streams subscribed are lost
+        eventually("the project streams", || async {
+            let subscribed = ["events", "interv_users"].iter().all(|manager| {
+                metrics::LIVE_STREAMS
+                    .with_label_values(&[manager, center, project])
+                    .get()
+                    > 0
+            });
+
+            subscribed.then_some(())
+        })
+        .await;
+    }
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Runs `sql` in `session` and returns the result of its last statement.
+pub async fn take<T>(session: &Session, sql: &str) -> Option<T>
+where
+    T: DeserializeOwned,
+    usize: QueryResult<T>,
+{
+    let mut res = session.query(sql).await.ok()?;
+
+    res.take(res.num_statements() - 1).ok()
+}
+
+/// Polls `check` until it returns a value, failing the test after a while.
+pub async fn eventually<T, F, Fut>(what: &str, mut check: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let polling = async {
+        loop {
+            if let Some(value) = check().await {
+                return value;
+            }
+
+            tokio::time::sleep(POLL).await;
+        }
+    };
+
+    tokio::time::timeout(TIMEOUT, polling)
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting for {what}"))
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use q_api_super::modules::projects::drift;
+
+use common::Harness;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn drift_report_lists_what_differs_from_the_template() {
+    let harness = Harness::start().await;
+    harness.create_project("center_kept", "project_kept").await;
+    harness
+        .create_project("center_drift", "project_drifted")
+        .await;
+
+    let report = || async {
+        let mut out = Vec::new();
+        let drifted = drift::report(&harness.db, &harness.config, &mut out)
+            .await
+            .unwrap();
+
+        (drifted, String::from_utf8(out).unwrap())
+    };
+
+    assert_eq!(
+        report().await,
+        (
+            false,
+            "center_drift/project_drifted: in sync\ncenter_kept/project_kept: in sync\n"
+                .to_string()
+        )
+    );
+
+    harness
+        .project("center_drift", "project_drifted")
+        .execute(
+            r#"
+            REMOVE TABLE cron_log;
+            DEFINE INDEX by_role ON users FIELDS role;
+            DEFINE FUNCTION fn::on_cron($script: string) {
+                RETURN NONE;
+            };
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let (drifted, out) = report().await;
+    assert!(drifted);
+    assert_eq!(
+        out,
+        "center_drift/project_drifted:\n\
+         \x20 - table cron_log\n\
+         \x20 + index users.by_role\n\
+         \x20 ~ function fn::on_cron\n\
+         center_kept/project_kept: in sync\n"
+    );
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use q_api_super::metrics;
+use serde::Deserialize;
+use surrealdb::sql::Uuid;
+
+use common::{eventually, take, Harness};
+
+#[derive(Debug, Deserialize)]
+struct EventStatus {
+    active: bool,
+    status: Option<String>,
+    job_id: Option<Uuid>,
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn scheduled_event_runs_until_done() {
+    let harness = Harness::start().await;
+    harness
+        .create_project("center_events", "project_events")
+        .await;
+
+    let project = harness.project("center_events", "project_events");
+    project
+        .execute(
+            r#"
+            CREATE events:tick SET
+                active = true,
+                script = "tick 'quoted'",
+                schedule = "*/1 * * * * *",
+                until = time::now() + 2500ms;
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let transitions: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let last: EventStatus = eventually("the event to be done", || {
+        let project = project.clone();
+        let transitions = &transitions;
+
+        async move {
+            let event: EventStatus = take::<Option<EventStatus>>(
+                &project,
+                "SELECT active, status, job_id FROM ONLY events:tick;",
+            )
+            .await
+            .flatten()?;
+
+            if let Some(status) = &event.status {
+                let mut transitions = transitions.lock().unwrap();
+                if transitions.last() != Some(status) {
+                    transitions.push(status.clone());
+                }
+            }
+
+            (event.status.as_deref() == Some("done")).then_some(event)
+        }
+    })
+    .await;
+
+    let transitions = transitions.into_inner().unwrap();
+    assert_eq!(transitions, ["scheduled", "running", "done"]);
+    assert!(!last.active);
+    assert!(last.job_id.is_none());
+
+    let runs: Vec<String> = take(&project, "SELECT VALUE script FROM cron_log;")
+        .await
+        .unwrap();
+    assert!(!runs.is_empty());
+    // the script reaches the function as it is, quotes and all
+    assert!(runs.iter().all(|script| script == "tick 'quoted'"));
+
+    // a finished event must stay finished once its job is gone
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let after: EventStatus = take::<Option<EventStatus>>(
+        &project,
+        "SELECT active, status, job_id FROM ONLY events:tick;",
+    )
+    .await
+    .flatten()
+    .unwrap();
+    assert_eq!(after.status.as_deref(), Some("done"));
+
+    // a deleted event stops firing
+    project
+        .execute(
+            r#"
+            CREATE events:tock SET
+                active = true,
+                script = "tock",
+                schedule = "*/1 * * * * *";
+            "#,
+        )
+        .await
+        .unwrap();
+    let jobs = metrics::SCHEDULED_JOBS.with_label_values(&["center_events", "project_events"]);
+    eventually("the event to be scheduled", || async {
+        (jobs.get() == 1).then_some(())
+    })
+    .await;
+
+    project.execute("DELETE events:tock;").await.unwrap();
+    eventually("the job to be removed", || async {
+        (jobs.get() == 0).then_some(())
+    })
+    .await;
+}
@@ -0,0 +1,31 @@
+#!/bin/sh
+# Regenerates the self-signed CA and the server and client certificates used
+# by the TLS tests, valid for 100 years.
+set -e
+cd "$(dirname "$0")"
+
+days=36500
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days $days \
+    -subj "/CN=q-api-super test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -keyout ca.key -out ca.pem
+
+sign() {
+    name=$1
+    ext=$2
+
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes \
+        -subj "/CN=$name" -keyout "$name.key" -out "$name.csr"
+    printf "basicConstraints=CA:FALSE\n%s\n" "$ext" > "$name.ext"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial \
+        -days $days -extfile "$name.ext" -out "$name.pem"
+    rm "$name.csr" "$name.ext"
+}
+
+sign server "subjectAltName=DNS:localhost,IP:127.0.0.1
+extendedKeyUsage=serverAuth"
+sign client "extendedKeyUsage=clientAuth"
+
+rm -f ca.srl
@@ -0,0 +1,88 @@
+mod common;
+
+use surrealdb::sql::Value;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center; REMOVE NAMESPACE global; --";
+const PROJECT: &str = "it's `a` project";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn hostile_names_are_only_names() {
+    let harness = Harness::start().await;
+    harness.create_project(CENTER, PROJECT).await;
+
+    let project = harness.project(CENTER, PROJECT);
+    project
+        .execute(
+            r#"
+            CREATE events:tick SET
+                active = true,
+                script = "tick",
+                schedule = "*/1 * * * * *";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    eventually("a run", || async {
+        take::<Vec<String>>(&project, "SELECT VALUE script FROM cron_log;")
+            .await
+            .filter(|runs| !runs.is_empty())
+    })
+    .await;
+
+    // nothing ran outside the project database
+    let projects: Vec<String> = take(&harness.main(), "SELECT VALUE name FROM projects;")
+        .await
+        .unwrap();
+    assert_eq!(projects, [PROJECT]);
+
+    let kv: Value = take(&harness.main(), "INFO FOR KV;").await.unwrap();
+    assert!(kv.pick(&["namespaces".into(), "global".into()]).is_some());
+
+    // one refused on creation is not backed up next to the backups either
+    harness
+        .project("..", "escape")
+        .execute("CREATE notes SET text = 'left behind';")
+        .await
+        .unwrap();
+    harness
+        .main()
+        .execute(
+            r#"
+            CREATE centers:dots SET name = "..";
+            CREATE projects:escape SET
+                name = "escape",
+                center = centers:dots,
+                state = "active",
+                token = "secret";
+            DELETE projects:escape;
+            "#,
+        )
+        .await
+        .unwrap();
+
+    // the deletions are handled in order, once this one is archived the
+    // other was refused
+    harness
+        .main()
+        .query("DELETE projects WHERE center.name = $b_center;")
+        .bind(("b_center", CENTER))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+    let center = harness.backups.path().join(CENTER);
+    eventually("the archive of the hostile project", || async {
+        std::fs::read_dir(&center).ok()?.next().map(|_| ())
+    })
+    .await;
+
+    let outside = harness.backups.path().parent().unwrap();
+    let escaped = std::fs::read_dir(outside)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("escape-"));
+    assert!(!escaped, "backed up outside of the backup directory");
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{eventually, take, Harness};
+
+async fn relate(harness: &Harness, user: &str, role: &str) {
+    harness
+        .main()
+        .query(
+            r#"
+            CREATE type::thing("users", $b_user) SET username = $b_user;
+            RELATE (type::thing("users", $b_user))->roled->centers:center_users SET role = $b_role;
+            RELATE (type::thing("users", $b_user))->join->projects:project_users SET
+                created = time::now(),
+                updated = time::now();
+            "#,
+        )
+        .bind(("b_user", user))
+        .bind(("b_role", role))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn project_user_state_is_copied_to_the_join() {
+    let harness = Harness::start().await;
+    harness
+        .create_project("center_users", "project_users")
+        .await;
+
+    relate(&harness, "carol", "guest").await;
+
+    let main = harness.main();
+    let state_of_carol = || async {
+        take::<Option<String>>(
+            &main,
+            "SELECT VALUE state FROM ONLY join WHERE in = users:carol LIMIT 1;",
+        )
+        .await
+        .flatten()
+    };
+
+    eventually("the join state", || async {
+        state_of_carol().await.filter(|state| state == "active")
+    })
+    .await;
+
+    let project = harness.project("center_users", "project_users");
+    project
+        .execute("UPDATE users:carol SET state = 'standby';")
+        .await
+        .unwrap();
+
+    eventually("the standby join state", || async {
+        state_of_carol().await.filter(|state| state == "standby")
+    })
+    .await;
+
+    // an admin editing the join reaches the project user
+    main.execute("UPDATE join SET state = 'active' WHERE in = users:carol;")
+        .await
+        .unwrap();
+
+    let version = || async {
+        take::<Option<u64>>(
+            &project,
+            "SELECT VALUE state_version FROM ONLY users:carol;",
+        )
+        .await
+        .flatten()
+    };
+    eventually("the active project user", || async {
+        take::<Option<String>>(&project, "SELECT VALUE state FROM ONLY users:carol;")
+            .await
+            .flatten()
+            .filter(|state| state == "active")
+    })
+    .await;
+
+    // the echoes of the sync do not bounce back and forth
+    let settled = version().await;
+    assert!(settled.is_some());
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(version().await, settled);
+    assert_eq!(state_of_carol().await.as_deref(), Some("active"));
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use serde::Deserialize;
+
+use common::{eventually, take, Harness};
+
+#[derive(Debug, Deserialize)]
+struct ProjectUser {
+    role: String,
+    state: String,
+}
+
+async fn relate(harness: &Harness, user: &str, role: &str) {
+    harness
+        .main()
+        .query(
+            r#"
+            CREATE type::thing("users", $b_user) SET username = $b_user;
+            RELATE (type::thing("users", $b_user))->roled->centers:center_join SET role = $b_role;
+            RELATE (type::thing("users", $b_user))->join->projects:project_join SET
+                created = time::now(),
+                updated = time::now();
+            "#,
+        )
+        .bind(("b_user", user))
+        .bind(("b_role", role))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn participant_join_comes_and_goes_with_the_project_user() {
+    let harness = Harness::start().await;
+    harness.create_project("center_join", "project_join").await;
+
+    relate(&harness, "alice", "parti").await;
+    relate(&harness, "bob", "admin").await;
+
+    let project = harness.project("center_join", "project_join");
+    let user: ProjectUser = eventually("the project user", || async {
+        take::<Option<ProjectUser>>(&project, "SELECT * FROM ONLY users:alice;")
+            .await
+            .flatten()
+    })
+    .await;
+
+    assert_eq!(user.role, "parti");
+    assert_eq!(user.state, "active");
+
+    let main = harness.main();
+    eventually("the join state", || async {
+        take::<Option<String>>(
+            &main,
+            "SELECT VALUE state FROM ONLY join WHERE in = users:alice LIMIT 1;",
+        )
+        .await
+        .flatten()
+        .filter(|state| state == "active")
+    })
+    .await;
+
+    // only participant roles get a user inside the project
+    let bob: Option<ProjectUser> = take(&project, "SELECT * FROM ONLY users:bob;")
+        .await
+        .unwrap();
+    assert!(bob.is_none());
+
+    // leaving removes the project user and the project of the user
+    main.execute("UPDATE users:alice SET project = projects:project_join;")
+        .await
+        .unwrap();
+    main.execute("DELETE join WHERE in = users:alice;")
+        .await
+        .unwrap();
+
+    eventually("the project user gone", || async {
+        take::<Option<ProjectUser>>(&project, "SELECT * FROM ONLY users:alice;")
+            .await
+            .is_some_and(|user| user.is_none())
+            .then_some(())
+    })
+    .await;
+
+    eventually("the user without project", || async {
+        take::<Option<bool>>(&main, "RETURN users:alice.project IS NONE;")
+            .await
+            .flatten()
+            .filter(|cleared| *cleared)
+    })
+    .await;
+}
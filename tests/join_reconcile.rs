@@ -0,0 +1,82 @@
+mod common;
+
+use q_api_super::metrics;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_reconcile";
+const PROJECT: &str = "project_reconcile";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn lost_project_users_come_back_and_strays_are_reported() {
+    let harness = Harness::start_with(|config| config.joins.reconcile_interval = 1).await;
+    harness.create_project(CENTER, PROJECT).await;
+
+    harness
+        .main()
+        .query(
+            r#"
+            CREATE users:erin SET username = "erin", project = projects:project_reconcile;
+            RELATE users:erin->roled->centers:center_reconcile SET role = "parti";
+            RELATE users:erin->join->projects:project_reconcile SET
+                created = time::now(),
+                updated = time::now();
+            CREATE users:frank SET project = projects:project_reconcile;
+            "#,
+        )
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+    let project = harness.project(CENTER, PROJECT);
+    let state_of_erin = || async {
+        take::<Option<String>>(&project, "SELECT VALUE state FROM ONLY users:erin;")
+            .await
+            .flatten()
+    };
+    eventually("the project user", || async {
+        state_of_erin().await.filter(|state| state == "active")
+    })
+    .await;
+
+    // lost while nobody was listening, and one nobody joined
+    project
+        .execute("DELETE users:erin; CREATE users:mallory SET role = 'parti';")
+        .await
+        .unwrap();
+
+    eventually("the restored project user", || async {
+        state_of_erin().await.filter(|state| state == "active")
+    })
+    .await;
+
+    let repairs = metrics::JOIN_REPAIRS.with_label_values(&[CENTER, PROJECT, "user"]);
+    eventually("the repair counted", || async {
+        (repairs.get() >= 1).then_some(())
+    })
+    .await;
+
+    let unjoined = metrics::UNJOINED_USERS.with_label_values(&[CENTER, PROJECT]);
+    eventually("the stray user reported", || async {
+        (unjoined.get() == 1).then_some(())
+    })
+    .await;
+
+    // a project set before its join exists is not touched
+    for user in ["erin", "frank"] {
+        let cleared: Option<bool> = take(
+            &harness.main(),
+            &format!("RETURN users:{user}.project IS NONE;"),
+        )
+        .await
+        .flatten();
+        assert_eq!(cleared, Some(false), "the project of {user} cleared");
+    }
+
+    // the stray one is left alone
+    let mallory: Option<String> = take(&project, "SELECT VALUE role FROM ONLY users:mallory;")
+        .await
+        .flatten();
+    assert_eq!(mallory.as_deref(), Some("parti"));
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use q_api_super::metrics;
+use q_api_super::modules::projects::migrations::Migrations;
+use surrealdb::sql::Value;
+use tempdir::TempDir;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_migrations";
+const PROJECT: &str = "project_migrations";
+const VERSIONS: &str = "SELECT VALUE version FROM migration ORDER BY version;";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn migrations_reach_the_template_and_every_project() {
+    let dir = TempDir::new("migrations").unwrap();
+    let write = |file: &str, script: &str| std::fs::write(dir.path().join(file), script).unwrap();
+
+    write(
+        "0001_flags.surql",
+        "DEFINE TABLE flags SCHEMALESS;\nDEFINE FIELD on ON flags TYPE bool DEFAULT false;\n",
+    );
+    write(
+        "0002_on_cron.surql",
+        r#"
+        DEFINE FUNCTION fn::on_cron($script: string) {
+            CREATE cron_log SET script = "v2:" + $script, at = time::now();
+            RETURN NONE;
+        };
+        "#,
+    );
+
+    let path = dir.path().to_path_buf();
+    let harness = Harness::start_with(|config| config.migrations.dir = Some(path)).await;
+    let namespaces = &harness.config.namespaces;
+
+    // the template is migrated first, new projects inherit it
+    let template = harness.project(&namespaces.global, &namespaces.template);
+    let versions: Vec<u32> = take(&template, VERSIONS).await.unwrap();
+    assert_eq!(versions, [1, 2]);
+
+    harness.create_project(CENTER, PROJECT).await;
+    let project = harness.project(CENTER, PROJECT);
+
+    eventually("the project migrations", || async {
+        take::<Vec<u32>>(&project, VERSIONS)
+            .await
+            .filter(|versions| versions == &[1, 2])
+    })
+    .await;
+
+    // nothing left for the creation to apply, later files stay pending
+    let settled =
+        format!(r#"supervisor_migrations_pending{{center="{CENTER}",project="{PROJECT}"}} 0"#);
+    eventually("the creation migrations", || async {
+        metrics::render().contains(&settled).then_some(())
+    })
+    .await;
+
+    let info: Value = take(&project, "INFO FOR DB;").await.unwrap();
+    assert!(info.pick(&["tables".into(), "flags".into()]).is_some());
+
+    // later migrations are pending until applied, one transaction each
+    write("0003_notes.surql", "DEFINE TABLE notes SCHEMALESS;\n");
+    write(
+        "0004_broken.surql",
+        "DEFINE TABLE half SCHEMALESS;\nTHROW 'broken';\n",
+    );
+
+    let migrations = Migrations::new(harness.db.clone(), harness.config.clone());
+    let pending = metrics::MIGRATIONS_PENDING.with_label_values(&[CENTER, PROJECT]);
+
+    assert_eq!(migrations.report(CENTER, PROJECT).await.unwrap(), 2);
+    assert_eq!(pending.get(), 2);
+
+    let error = migrations.apply(CENTER, PROJECT).await.unwrap_err();
+    assert!(error.to_string().contains("broken"), "{error}");
+    assert_eq!(pending.get(), 1);
+
+    let versions: Vec<u32> = take(&project, VERSIONS).await.unwrap();
+    assert_eq!(versions, [1, 2, 3]);
+
+    let info: Value = take(&project, "INFO FOR DB;").await.unwrap();
+    assert!(info.pick(&["tables".into(), "notes".into()]).is_some());
+    assert!(info.pick(&["tables".into(), "half".into()]).is_none());
+
+    // a project created now cannot be completed, its database is dropped
+    harness
+        .main()
+        .execute(
+            r#"
+            CREATE projects:project_broken SET
+                name = "project_broken",
+                center = centers:center_migrations,
+                state = "active",
+                token = "secret";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    eventually("the recorded failure", || async {
+        take::<Option<String>>(
+            &harness.main(),
+            "SELECT VALUE migration_status FROM ONLY projects:project_broken;",
+        )
+        .await
+        .flatten()
+        .filter(|status| status == "failed")
+    })
+    .await;
+
+    let broken = harness.project(CENTER, "project_broken");
+    let info: Value = take(&broken, "INFO FOR DB;").await.unwrap_or_default();
+    assert!(info.pick(&["tables".into(), "users".into()]).is_none());
+    assert_eq!(
+        metrics::MIGRATION_FAILURES
+            .with_label_values(&[CENTER, "project_broken"])
+            .get(),
+        1
+    );
+
+    // nothing was subscribed to the database that is gone
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    for manager in ["events", "interv_users"] {
+        let live = metrics::LIVE_STREAMS.with_label_values(&[manager, CENTER, "project_broken"]);
+        assert_eq!(live.get(), 0, "{manager}");
+    }
+}
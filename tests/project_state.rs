@@ -0,0 +1,140 @@
+mod common;
+
+use std::time::Duration;
+
+use q_api_super::metrics;
+use surrealdb::sql::Value;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_state";
+const PROJECT: &str = "project_state";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn project_state_drives_events_users_and_permissions() {
+    let harness = Harness::start().await;
+    harness.create_project(CENTER, PROJECT).await;
+
+    let main = harness.main();
+    main.query(
+        r#"
+        CREATE users:dave SET username = "dave";
+        RELATE users:dave->roled->(type::thing("centers", $b_center)) SET role = "guest";
+        RELATE users:dave->join->(type::thing("projects", $b_project)) SET
+            created = time::now(),
+            updated = time::now();
+        "#,
+    )
+    .bind(("b_center", CENTER))
+    .bind(("b_project", PROJECT))
+    .await
+    .unwrap()
+    .check()
+    .unwrap();
+
+    let project = harness.project(CENTER, PROJECT);
+    project
+        .execute(
+            r#"
+            DEFINE TABLE scores SCHEMALESS PERMISSIONS FULL;
+            CREATE events:tick SET
+                active = true,
+                script = "tick",
+                schedule = "0 0 0 1 1 *";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let jobs = metrics::SCHEDULED_JOBS.with_label_values(&[CENTER, PROJECT]);
+    let user_state = || async {
+        take::<Option<String>>(&project, "SELECT VALUE state FROM ONLY users:dave;")
+            .await
+            .flatten()
+    };
+    let join_state = || async {
+        take::<Option<String>>(
+            &main,
+            "SELECT VALUE state FROM ONLY join WHERE in = users:dave LIMIT 1;",
+        )
+        .await
+        .flatten()
+    };
+    let event_status = || async {
+        take::<Option<String>>(&project, "SELECT VALUE status FROM ONLY events:tick;")
+            .await
+            .flatten()
+    };
+    let set_state = |state: &'static str| {
+        let main = main.clone();
+
+        async move {
+            main.query(r#"UPDATE type::thing("projects", $b_project) SET state = $b_state;"#)
+                .bind(("b_project", PROJECT))
+                .bind(("b_state", state))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+    };
+
+    eventually("the user and the job", || async {
+        let active = user_state().await.filter(|state| state == "active");
+
+        active.filter(|_| jobs.get() == 1)
+    })
+    .await;
+
+    set_state("paused").await;
+    eventually("the paused project", || async {
+        let paused = event_status().await.filter(|status| status == "paused");
+        let standby = join_state().await.filter(|state| state == "standby");
+
+        paused.and(standby).filter(|_| jobs.get() == 0)
+    })
+    .await;
+    assert_eq!(user_state().await.as_deref(), Some("standby"));
+
+    // a typo is no state, the project stays paused
+    set_state("activ").await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(join_state().await.as_deref(), Some("standby"));
+    assert_eq!(event_status().await.as_deref(), Some("paused"));
+    assert_eq!(jobs.get(), 0);
+
+    set_state("active").await;
+    eventually("the resumed project", || async {
+        let scheduled = event_status().await.filter(|status| status == "scheduled");
+        let active = join_state().await.filter(|state| state == "active");
+
+        scheduled.and(active).filter(|_| jobs.get() == 1)
+    })
+    .await;
+
+    set_state("finished").await;
+    eventually("the finished project", || async {
+        let completed = join_state().await.filter(|state| state == "completed");
+
+        completed.filter(|_| jobs.get() == 0)
+    })
+    .await;
+
+    let active: Option<bool> = take(&project, "SELECT VALUE active FROM ONLY events:tick;")
+        .await
+        .flatten();
+    assert_eq!(active, Some(false));
+
+    // the scope loses every write permission, reads stay as they were
+    let scores: Value = eventually("the read-only tables", || async {
+        let info: Value = take(&project, "INFO FOR DB;").await?;
+        let scores = info.pick(&["tables".into(), "scores".into()]);
+
+        scores
+            .to_raw_string()
+            .contains("FOR create, update, delete NONE")
+            .then_some(scores)
+    })
+    .await;
+    assert!(scores.to_raw_string().contains("FOR select FULL"));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use q_api_super::health;
+use surrealdb::sql::Value;
+
+use common::{eventually, take, Harness};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn project_creation_imports_the_template() {
+    let harness = Harness::start().await;
+
+    harness.create_project("center_one", "project_one").await;
+
+    let session = harness.project("center_one", "project_one");
+    let info: Value = take(&session, "INFO FOR DB;").await.unwrap();
+
+    for table in ["users", "events", "scores"] {
+        let path = ["tables".into(), table.into()];
+        assert!(info.pick(&path).is_some(), "missing table {table}");
+    }
+
+    // the project database is usable by the function the events rely on
+    let result: Value = take(&session, "fn::on_cron('probe');").await.unwrap();
+    assert!(result.is_none());
+
+    eventually("the cron log entry", || async {
+        take::<Vec<String>>(&session, "SELECT VALUE script FROM cron_log;")
+            .await
+            .filter(|logs| logs == &["probe"])
+    })
+    .await;
+
+    assert_eq!(health::registry().readiness(), Ok(()));
+    assert_eq!(health::registry().liveness(), Ok(()));
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use std::sync::Arc;
+
+use q_api_super::metrics;
+use q_api_super::modules::projects::rollout::Rollout;
+use surrealdb::sql::Value;
+use tempdir::TempDir;
+
+use common::{eventually, take, Harness};
+
+const VERSIONS: &str = "SELECT VALUE version FROM migration ORDER BY version;";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn rollout_goes_in_waves_and_halts_at_a_failing_canary() {
+    let dir = TempDir::new("migrations").unwrap();
+    let write = |file: &str, script: &str| std::fs::write(dir.path().join(file), script).unwrap();
+    write("0001_flags.surql", "DEFINE TABLE flags SCHEMALESS;\n");
+
+    let path = dir.path().to_path_buf();
+    let harness = Harness::start_with(|config| config.migrations.dir = Some(path)).await;
+
+    let tenants = [
+        ("center_canary", "project_canary"),
+        ("center_rest", "project_one"),
+        ("center_wave", "project_two"),
+    ];
+    for (center, project) in tenants {
+        harness.create_project(center, project).await;
+
+        // the creation applies the migrations the template dump missed, a
+        // later file must not reach the project that way
+        let settled =
+            format!(r#"supervisor_migrations_pending{{center="{center}",project="{project}"}} 0"#);
+        eventually("the creation migrations", || async {
+            metrics::render().contains(&settled).then_some(())
+        })
+        .await;
+    }
+
+    let mut config = (*harness.config).clone();
+    config.rollout.canaries = vec!["center_canary/project_canary".to_string()];
+    config.rollout.wave = 1;
+    config.rollout.checks = vec!["fn::on_cron('rollout');".to_string()];
+    let rollout = Rollout::new(harness.db.clone(), Arc::new(config));
+
+    // every project, canary first, and each one passed the checks
+    write(
+        "0002_on_cron.surql",
+        r#"
+        DEFINE FUNCTION fn::on_cron($script: string) {
+            CREATE cron_log SET script = "v2:" + $script, at = time::now();
+            RETURN NONE;
+        };
+        "#,
+    );
+
+    let report = rollout.run().await.unwrap();
+    let migrated: Vec<String> = report.migrated.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        migrated,
+        [
+            "center_canary/project_canary",
+            "center_rest/project_one",
+            "center_wave/project_two",
+        ]
+    );
+    assert!(report.halted.is_empty());
+    assert_eq!(metrics::ROLLOUT_HALTED.get(), 0);
+
+    for (center, project) in tenants {
+        let runs: Vec<String> = take(
+            &harness.project(center, project),
+            "SELECT VALUE script FROM cron_log;",
+        )
+        .await
+        .unwrap();
+        assert_eq!(runs, ["v2:rollout"]);
+    }
+
+    // the canary cannot take a unique index, nobody else gets it
+    harness
+        .project("center_canary", "project_canary")
+        .execute("CREATE users SET role = 'guest'; CREATE users SET role = 'guest';")
+        .await
+        .unwrap();
+    write(
+        "0003_unique_role.surql",
+        "DEFINE INDEX unique_role ON users FIELDS role UNIQUE;\n",
+    );
+
+    let report = rollout.run().await.unwrap();
+    assert!(report.migrated.is_empty());
+
+    let halted: Vec<String> = report.halted.iter().map(|(t, _)| t.to_string()).collect();
+    assert_eq!(halted, ["center_canary/project_canary"]);
+    // the statement that failed, not the ones the transaction took along
+    let error = report.halted[0].1.to_string();
+    assert!(error.contains("unique_role"), "{error}");
+
+    let remaining: Vec<String> = report.remaining.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        remaining,
+        ["center_rest/project_one", "center_wave/project_two"]
+    );
+    assert_eq!(metrics::ROLLOUT_HALTED.get(), 1);
+
+    for (center, project) in tenants {
+        let versions: Vec<u32> = take(&harness.project(center, project), VERSIONS)
+            .await
+            .unwrap();
+        assert_eq!(versions, [1, 2], "{center}/{project}");
+    }
+
+    let failures =
+        metrics::MIGRATION_FAILURES.with_label_values(&["center_canary", "project_canary"]);
+    assert_eq!(failures.get(), 1);
+
+    // a failing check takes the migrations of the canary back with it
+    std::fs::remove_file(dir.path().join("0003_unique_role.surql")).unwrap();
+    write("0003_notes.surql", "DEFINE TABLE notes SCHEMALESS;\n");
+
+    let mut config = (*harness.config).clone();
+    config.rollout.canaries = vec!["center_canary/project_canary".to_string()];
+    config.rollout.checks = vec!["fn::missing();".to_string()];
+    let report = Rollout::new(harness.db.clone(), Arc::new(config))
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(report.halted.len(), 1);
+    assert_eq!(failures.get(), 2);
+
+    let canary = harness.project("center_canary", "project_canary");
+    let versions: Vec<u32> = take(&canary, VERSIONS).await.unwrap();
+    assert_eq!(versions, [1, 2]);
+
+    let info: Value = take(&canary, "INFO FOR DB;").await.unwrap();
+    assert!(info.pick(&["tables".into(), "notes".into()]).is_none());
+    assert!(info.pick(&["tables".into(), "users".into()]).is_some());
+
+    let users: Vec<String> = take(&canary, "SELECT VALUE role FROM users;")
+        .await
+        .unwrap();
+    assert_eq!(users, ["guest", "guest"]);
+
+    // the database was never dropped, its streams are still there
+    let streams =
+        metrics::LIVE_STREAMS.with_label_values(&["events", "center_canary", "project_canary"]);
+    assert_eq!(streams.get(), 1);
+
+    let status: Option<String> = take(
+        &harness.main(),
+        "SELECT VALUE migration_status FROM ONLY projects:project_canary;",
+    )
+    .await
+    .flatten();
+    assert_eq!(status.as_deref(), Some("failed"));
+
+    // a wave is migrated as a whole, its failure keeps the next waves back
+    write(
+        "0004_unique_role.surql",
+        "DEFINE INDEX unique_role ON users FIELDS role UNIQUE;\n",
+    );
+
+    let mut config = (*harness.config).clone();
+    config.rollout.wave = 2;
+    let report = Rollout::new(harness.db.clone(), Arc::new(config.clone()))
+        .run()
+        .await
+        .unwrap();
+
+    let migrated: Vec<String> = report.migrated.iter().map(ToString::to_string).collect();
+    assert_eq!(migrated, ["center_rest/project_one"]);
+    let halted: Vec<String> = report.halted.iter().map(|(t, _)| t.to_string()).collect();
+    assert_eq!(halted, ["center_canary/project_canary"]);
+    let remaining: Vec<String> = report.remaining.iter().map(ToString::to_string).collect();
+    assert_eq!(remaining, ["center_wave/project_two"]);
+
+    // a restart only migrates the canaries
+    config.rollout.canaries = vec!["center_wave/project_two".to_string()];
+    let report = Rollout::new(harness.db.clone(), Arc::new(config))
+        .on_start()
+        .await
+        .unwrap();
+
+    let migrated: Vec<String> = report.migrated.iter().map(ToString::to_string).collect();
+    assert_eq!(migrated, ["center_wave/project_two"]);
+    assert!(report.halted.is_empty());
+
+    let versions: Vec<u32> = take(&canary, VERSIONS).await.unwrap();
+    assert_eq!(versions, [1, 2]);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use serde::Deserialize;
+use surrealdb::sql::Uuid;
+
+use common::{eventually, take, Harness};
+
+#[derive(Debug, Deserialize)]
+struct EventStatus {
+    active: bool,
+    status: Option<String>,
+    job_id: Option<Uuid>,
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn shutdown_releases_running_events() {
+    let harness = Harness::start().await;
+    harness
+        .create_project("center_shutdown", "project_shutdown")
+        .await;
+
+    let project = harness.project("center_shutdown", "project_shutdown");
+    project
+        .execute(
+            r#"
+            CREATE events:tick SET
+                active = true,
+                script = "tick",
+                schedule = "*/1 * * * * *";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    eventually("the event to run", || async {
+        let event: EventStatus = take::<Option<EventStatus>>(
+            &project,
+            "SELECT active, status, job_id FROM ONLY events:tick;",
+        )
+        .await
+        .flatten()?;
+
+        (event.status.as_deref() == Some("running")).then_some(())
+    })
+    .await;
+
+    harness.stop().await;
+
+    let event: EventStatus = take::<Option<EventStatus>>(
+        &project,
+        "SELECT active, status, job_id FROM ONLY events:tick;",
+    )
+    .await
+    .flatten()
+    .unwrap();
+
+    // ready to be picked up again by the next start
+    assert!(event.active);
+    assert_eq!(event.status.as_deref(), Some("scheduled"));
+    assert!(event.job_id.is_none());
+}
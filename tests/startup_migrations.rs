@@ -0,0 +1,109 @@
+mod common;
+
+use std::path::PathBuf;
+
+use surrealdb::sql::Value;
+use tempdir::TempDir;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_existing";
+const VERSIONS: &str = "SELECT VALUE version FROM migration ORDER BY version;";
+
+/// Two projects that were there before the supervisor shipped its markers,
+/// with the schemafull users table of the template at that time.
+const SEED: &str = r#"
+CREATE centers:center_existing SET name = "center_existing";
+CREATE projects:project_canary SET
+    name = "project_canary",
+    center = centers:center_existing,
+    state = "active",
+    token = "secret";
+CREATE projects:project_held SET
+    name = "project_held",
+    center = centers:center_existing,
+    state = "active",
+    token = "secret";
+
+USE NS center_existing DB project_canary;
+DEFINE SCOPE user SESSION 1d;
+DEFINE TABLE users SCHEMAFULL;
+DEFINE FIELD role ON users TYPE string;
+DEFINE FIELD state ON users TYPE string DEFAULT 'active';
+
+USE NS center_existing DB project_held;
+DEFINE SCOPE user SESSION 1d;
+DEFINE TABLE users SCHEMAFULL;
+DEFINE FIELD role ON users TYPE string;
+DEFINE FIELD state ON users TYPE string DEFAULT 'active';
+"#;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn existing_projects_get_the_supervisor_migrations_on_start() {
+    let dir = TempDir::new("startup").unwrap();
+    let seed = dir.path().join("main.surql");
+    std::fs::write(&seed, SEED).unwrap();
+
+    // the shipped migrations, then one of the deployment
+    let migrations = dir.path().join("migrations");
+    std::fs::create_dir(&migrations).unwrap();
+    let shipped = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    for file in ["0001_state_markers.surql", "0002_standby_marker.surql"] {
+        std::fs::copy(shipped.join(file), migrations.join(file)).unwrap();
+    }
+    std::fs::write(
+        migrations.join("0003_notes.surql"),
+        "DEFINE TABLE notes SCHEMALESS;\n",
+    )
+    .unwrap();
+
+    let harness = Harness::start_with(|config| {
+        config.embedded.main = Some(seed);
+        config.migrations.dir = Some(migrations);
+        config.rollout.canaries = vec![format!("{CENTER}/project_canary")];
+    })
+    .await;
+
+    // the canary gets everything, the project held back the markers only
+    let canary = harness.project(CENTER, "project_canary");
+    eventually("the canary migrations", || async {
+        take::<Vec<u32>>(&canary, VERSIONS)
+            .await
+            .filter(|versions| versions == &[1, 2, 3])
+    })
+    .await;
+
+    let held = harness.project(CENTER, "project_held");
+    eventually("the supervisor migrations", || async {
+        take::<Vec<u32>>(&held, VERSIONS)
+            .await
+            .filter(|versions| versions == &[1, 2])
+    })
+    .await;
+
+    let status: Option<String> = take(
+        &harness.main(),
+        "SELECT VALUE migration_status FROM ONLY projects:project_held;",
+    )
+    .await
+    .flatten();
+    assert_eq!(status.as_deref(), Some("ok"));
+
+    let fields: Value = take(&held, "INFO FOR TABLE users;").await.unwrap();
+    for field in ["state_synced", "state_version", "state_origin", "paused"] {
+        assert!(
+            fields.pick(&["fields".into(), field.into()]).is_some(),
+            "no {field} on the users of a held back project"
+        );
+    }
+
+    // the schemafull table keeps them now
+    let paused: Option<bool> = take(
+        &held,
+        "CREATE ONLY users:ivy SET role = 'parti', state_synced = 'active', paused = true;
+        SELECT VALUE paused FROM ONLY users:ivy;",
+    )
+    .await
+    .flatten();
+    assert_eq!(paused, Some(true));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use std::time::Duration;
+
+use q_api_super::{health, metrics};
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_teardown";
+const PROJECT: &str = "project_teardown";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn deleted_project_releases_streams_and_jobs() {
+    let harness = Harness::start().await;
+    harness.create_project(CENTER, PROJECT).await;
+
+    let project = harness.project(CENTER, PROJECT);
+    project
+        .execute(
+            r#"
+            CREATE events:tick SET
+                active = true,
+                script = "tick",
+                schedule = "*/1 * * * * *";
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let jobs = metrics::SCHEDULED_JOBS.with_label_values(&[CENTER, PROJECT]);
+    let streams = ["events", "interv_users"]
+        .map(|manager| metrics::LIVE_STREAMS.with_label_values(&[manager, CENTER, PROJECT]));
+
+    eventually("the first run", || async {
+        take::<Vec<String>>(&project, "SELECT VALUE script FROM cron_log;")
+            .await
+            .filter(|runs| !runs.is_empty())
+    })
+    .await;
+    assert_eq!(jobs.get(), 1);
+    assert!(streams.iter().all(|gauge| gauge.get() == 1));
+
+    harness
+        .main()
+        .execute(&format!("DELETE projects:{PROJECT};"))
+        .await
+        .unwrap();
+
+    eventually("the resources to be released", || async {
+        (jobs.get() == 0 && streams.iter().all(|gauge| gauge.get() == 0)).then_some(())
+    })
+    .await;
+
+    // the streams are gone, not dead
+    assert_eq!(health::registry().liveness(), Ok(()));
+    assert_eq!(health::registry().readiness(), Ok(()));
+
+    // and the job stopped firing into the deleted project
+    let runs = "SELECT VALUE script FROM cron_log;";
+    let before: Vec<String> = take(&project, runs).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let after: Vec<String> = take(&project, runs).await.unwrap();
+    assert_eq!(before.len(), after.len());
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use q_api_super::config::{Config, Tls};
+use q_api_super::connection::{self, Session};
+
+/// Runs against a SurrealDB started with the test certificates, `root`/`root`
+/// and reachable at `$TLS_TEST_HOST` (`localhost:8000` when unset):
+///
+/// ``` bash
+/// surreal start --user root --pass root \
+///     --web-crt tests/fixtures/tls/server.pem --web-key tests/fixtures/tls/server.key
+/// cargo test --test tls -- --ignored
+/// ```
+#[tokio::test]
+#[ignore = "needs a SurrealDB server with the test certificate"]
+async fn self_signed_server_over_wss_and_https() {
+    let mut config = Config::default();
+    config.db.host = std::env::var("TLS_TEST_HOST").unwrap_or_else(|_| "localhost:8000".into());
+    config.db.tls = Tls {
+        ca: Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls/ca.pem")),
+        ..Default::default()
+    };
+    assert!(config.endpoint().starts_with("wss://"));
+
+    // the websocket client every manager uses
+    let db = connection::connect(&config).await;
+    let session = Session::new(&db, "tls", "tls");
+    let mut res = session.query("RETURN 1;").await.unwrap();
+    let answer: Option<i64> = res.take(res.num_statements() - 1).unwrap();
+    assert_eq!(answer, Some(1));
+
+    // the https client of exports and imports
+    let exclusive = connection::exclusive(&config).await.unwrap();
+    let mut res = exclusive.query("RETURN 2;").await.unwrap();
+    let answer: Option<i64> = res.take(0).unwrap();
+    assert_eq!(answer, Some(2));
+}
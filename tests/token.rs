@@ -0,0 +1,85 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use q_api_super::modules::projects::manager::ProjectsManager;
+use q_api_super::shutdown::Shutdown;
+use serde::Deserialize;
+
+use common::{eventually, take, Harness};
+
+const CENTER: &str = "center_token";
+const PROJECT: &str = "project_token";
+
+#[derive(Deserialize)]
+struct ScopeInfo {
+    tokens: BTreeMap<String, String>,
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn changed_token_is_rotated_with_a_grace_period() {
+    let harness = Harness::start().await;
+    harness.create_project(CENTER, PROJECT).await;
+
+    let project = harness.project(CENTER, PROJECT);
+    let tokens = || async {
+        take::<Option<ScopeInfo>>(&project, "INFO FOR SCOPE user;")
+            .await
+            .flatten()
+            .map(|info| info.tokens)
+    };
+
+    let defined = tokens().await.unwrap();
+    assert!(defined["user_scope"].contains("'secret'"));
+    assert!(!defined.contains_key("user_scope_previous"));
+
+    harness
+        .main()
+        .execute(&format!("UPDATE projects:{PROJECT} SET token = 'rotated';"))
+        .await
+        .unwrap();
+
+    // both secrets sign users in during the grace period
+    let rotated = eventually("the rotated token", || async {
+        tokens()
+            .await
+            .filter(|tokens| tokens["user_scope"].contains("'rotated'"))
+    })
+    .await;
+    assert!(rotated["user_scope_previous"].contains("'secret'"));
+
+    eventually("the end of the grace period", || async {
+        tokens()
+            .await
+            .filter(|tokens| !tokens.contains_key("user_scope_previous"))
+    })
+    .await;
+    // a restart in the middle of a grace period, with the token changed
+    // while nobody was listening
+    project
+        .execute(
+            r#"
+            DEFINE TOKEN user_scope ON SCOPE user TYPE HS256 VALUE 'stale';
+            DEFINE TOKEN user_scope_previous ON SCOPE user TYPE HS256 VALUE 'secret';
+            "#,
+        )
+        .await
+        .unwrap();
+
+    let shutdown = Shutdown::default();
+    let restarted = ProjectsManager::new(harness.config.clone(), shutdown.clone()).await;
+    let restarted = tokio::spawn(async move {
+        let _ = restarted.start().await;
+    });
+
+    eventually("the token restored from the project", || async {
+        tokens().await.filter(|tokens| {
+            tokens["user_scope"].contains("'rotated'")
+                && !tokens.contains_key("user_scope_previous")
+        })
+    })
+    .await;
+
+    shutdown.trigger();
+    restarted.await.unwrap();
+}